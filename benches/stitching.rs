@@ -0,0 +1,41 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use isolines::{paths_from_lines, CloseEdges, Field, Mode};
+
+// Egg-crate surface with a ring around every bump, so the number of paths grows
+// with the area of the field.
+fn egg_crate(size: u32) -> Field {
+    let vals = (0..size * size)
+        .map(|i| {
+            let (x, y) = ((i % size) as f32, (i / size) as f32);
+            (x / 3.0).sin() * (y / 3.0).sin()
+        })
+        .collect();
+    Field::new((size, size), vals).unwrap()
+}
+
+fn stitching(c: &mut Criterion) {
+    let mut group = c.benchmark_group("paths_from_lines");
+    for size in [128, 256, 512, 1024] {
+        let lines = egg_crate(size).raw_lines(0.25).unwrap();
+        group.throughput(Throughput::Elements(lines.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &lines, |b, lines| {
+            b.iter(|| paths_from_lines(lines, CloseEdges::ForExtent(size, size), Mode::Strict))
+        });
+    }
+    group.finish();
+}
+
+fn tracing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("trace_paths");
+    for size in [128, 256, 512, 1024] {
+        let field = egg_crate(size);
+        group.throughput(Throughput::Elements(u64::from(size * size)));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &field, |b, field| {
+            b.iter(|| field.trace_paths(0.25, Mode::Strict))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, stitching, tracing);
+criterion_main!(benches);
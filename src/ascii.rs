@@ -0,0 +1,352 @@
+//! Readers for rasters stored as text: Esri ASCII Grids and regular grids of
+//! XYZ points.
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path as FsPath,
+};
+
+use crate::{
+    error::{Error, Result},
+    geo::GeoTransform,
+    marching_squares::Field,
+};
+
+pub(crate) fn parse_error(line: usize, message: impl Into<String>) -> Error {
+    Error::Parse {
+        line: Some(line),
+        message: message.into(),
+    }
+}
+
+// A problem with the grid as a whole rather than with one line.
+pub(crate) fn grid_error(message: impl Into<String>) -> Error {
+    Error::Parse {
+        line: None,
+        message: message.into(),
+    }
+}
+
+pub(crate) fn number<T: std::str::FromStr>(line: usize, word: &str) -> Result<T> {
+    word.parse()
+        .map_err(|_| parse_error(line, format!("{word:?} isn't a number")))
+}
+
+// The lines of the reader, numbered from 1.
+pub(crate) fn lines(reader: impl BufRead) -> impl Iterator<Item = Result<(usize, String)>> {
+    reader
+        .lines()
+        .enumerate()
+        .map(|(i, line)| Ok((i + 1, line?)))
+}
+
+// The header of an ASCII Grid.
+#[derive(Default)]
+pub(crate) struct AsciiHeader {
+    ncols: Option<u32>,
+    nrows: Option<u32>,
+    // Lower left corner of the grid, and whether it's the centre of the
+    // corner cell rather than its outside corner.
+    x: Option<(f64, bool)>,
+    y: Option<(f64, bool)>,
+    cellsize: Option<(f64, f64)>,
+    pub(crate) nodata: Option<f32>,
+}
+
+impl AsciiHeader {
+    // Sets a value from line `n` of the header, unless the key isn't one of
+    // the grid's.
+    pub(crate) fn set(&mut self, n: usize, key: &str, value: &str) -> Result<bool> {
+        let cellsize = self.cellsize.unwrap_or((f64::NAN, f64::NAN));
+        match key.to_ascii_lowercase().as_str() {
+            "ncols" => self.ncols = Some(number(n, value)?),
+            "nrows" => self.nrows = Some(number(n, value)?),
+            "xllcorner" => self.x = Some((number(n, value)?, false)),
+            "xllcenter" => self.x = Some((number(n, value)?, true)),
+            "yllcorner" => self.y = Some((number(n, value)?, false)),
+            "yllcenter" => self.y = Some((number(n, value)?, true)),
+            "cellsize" => {
+                let size = number(n, value)?;
+                self.cellsize = Some((size, size));
+            }
+            "dx" => self.cellsize = Some((number(n, value)?, cellsize.1)),
+            "dy" => self.cellsize = Some((cellsize.0, number(n, value)?)),
+            "nodata_value" => self.nodata = Some(number(n, value)?),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // The extent of the grid and where its first value is on the map, which
+    // is the centre of the north west cell.
+    pub(crate) fn grid(&self) -> Result<((u32, u32), GeoTransform)> {
+        let missing = |key: &str| grid_error(format!("the header has no {key}"));
+        let ncols = self.ncols.ok_or_else(|| missing("ncols"))?;
+        let nrows = self.nrows.ok_or_else(|| missing("nrows"))?;
+        let (x, x_centre) = self.x.ok_or_else(|| missing("xllcorner"))?;
+        let (y, y_centre) = self.y.ok_or_else(|| missing("yllcorner"))?;
+        let (dx, dy) = self.cellsize.ok_or_else(|| missing("cellsize"))?;
+        if !(dx > 0.0 && dy > 0.0) {
+            return Err(grid_error("the cell size has to be positive"));
+        }
+        if ncols == 0 || nrows == 0 {
+            return Err(grid_error("the grid is empty"));
+        }
+
+        let left = if x_centre { x } else { x + dx / 2.0 };
+        let bottom = if y_centre { y } else { y + dy / 2.0 };
+        let top = bottom + (nrows - 1) as f64 * dy;
+        Ok((
+            (ncols, nrows),
+            GeoTransform::north_up((left, top), (dx, dy)),
+        ))
+    }
+}
+
+impl Field {
+    /// Loads an Esri ASCII Grid, keeping its georeferencing and nodata value.
+    pub fn open_ascii_grid(path: impl AsRef<FsPath>) -> Result<Self> {
+        Field::read_ascii_grid(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads an Esri ASCII Grid: a header of `ncols`, `nrows`, `xllcorner` or
+    /// `xllcenter`, `yllcorner` or `yllcenter`, `cellsize` (or `dx` and `dy`)
+    /// and an optional `NODATA_value`, followed by the values a row at a time
+    /// from the north.
+    pub fn read_ascii_grid(reader: impl BufRead) -> Result<Self> {
+        let mut header = AsciiHeader::default();
+        let mut vals = Vec::new();
+        for line in lines(reader) {
+            let (n, line) = line?;
+            let mut words = line.split_whitespace().peekable();
+            let Some(&first) = words.peek() else {
+                continue;
+            };
+            if vals.is_empty() && first.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                let (Some(key), Some(value), None) = (words.next(), words.next(), words.next())
+                else {
+                    return Err(parse_error(n, "expected a header key and a value"));
+                };
+                if !header.set(n, key, value)? {
+                    return Err(parse_error(n, format!("unknown header key {key:?}")));
+                }
+                continue;
+            }
+            for word in words {
+                vals.push(number(n, word)?);
+            }
+        }
+
+        let (extent, transform) = header.grid()?;
+        let expected = extent.0 as usize * extent.1 as usize;
+        if vals.len() != expected {
+            return Err(grid_error(format!(
+                "{} values don't fill the {}x{} grid of {expected}",
+                vals.len(),
+                extent.0,
+                extent.1
+            )));
+        }
+        let mut field = Field::new(extent, vals)?.with_transform(transform);
+        field.nodata = header.nodata;
+        Ok(field)
+    }
+
+    /// Loads a regular grid of XYZ points.
+    pub fn open_xyz(path: impl AsRef<FsPath>) -> Result<Self> {
+        Field::read_xyz(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads a regular grid of points, one `x y z` per line separated by
+    /// spaces, tabs, commas or semicolons, in any order. A first line that
+    /// isn't numbers is taken as a header and lines starting with `#` are
+    /// skipped. Every point of the grid has to be there exactly once, with
+    /// the same spacing throughout.
+    pub fn read_xyz(reader: impl BufRead) -> Result<Self> {
+        let mut points: Vec<(f64, f64, f32, usize)> = Vec::new();
+        for line in lines(reader) {
+            let (n, line) = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let words: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .filter(|word| !word.is_empty())
+                .collect();
+            let header = n == 1 && words.iter().any(|word| word.parse::<f64>().is_err());
+            if header {
+                continue;
+            }
+            let &[x, y, z] = words.as_slice() else {
+                return Err(parse_error(
+                    n,
+                    format!("expected x, y and z but found {} values", words.len()),
+                ));
+            };
+            points.push((number(n, x)?, number(n, y)?, number(n, z)?, n));
+        }
+        if points.is_empty() {
+            return Err(grid_error("there are no points"));
+        }
+
+        // The sorted distinct coordinates along an axis, and their spacing.
+        let axis = |coordinate: fn(&(f64, f64, f32, usize)) -> f64,
+                    name: &str|
+         -> Result<(Vec<f64>, f64)> {
+            let mut values: Vec<f64> = points.iter().map(coordinate).collect();
+            values.sort_by(f64::total_cmp);
+            values.dedup();
+            if values.len() == 1 {
+                return Ok((values, 1.0));
+            }
+            let spacing = (values[values.len() - 1] - values[0]) / (values.len() - 1) as f64;
+            for (i, &value) in values.iter().enumerate() {
+                let expected = values[0] + i as f64 * spacing;
+                if (value - expected).abs() > spacing * 1e-3 {
+                    return Err(grid_error(format!(
+                        "the {name} coordinates aren't evenly spaced, {value} is off the grid of {spacing}"
+                    )));
+                }
+            }
+            Ok((values, spacing))
+        };
+        let (xs, dx) = axis(|p| p.0, "x")?;
+        let (ys, dy) = axis(|p| p.1, "y")?;
+        let (left, top) = (xs[0], ys[ys.len() - 1]);
+
+        // Scattered points have many distinct coordinates along both axes,
+        // so the count is checked before making room for the whole grid.
+        let (w, h) = (xs.len(), ys.len());
+        match w.checked_mul(h) {
+            Some(cells) if cells == points.len() => (),
+            Some(cells) => {
+                return Err(grid_error(format!(
+                    "{} points don't fill the {w}x{h} grid of {cells}",
+                    points.len()
+                )))
+            }
+            None => {
+                return Err(grid_error(format!(
+                    "{} points don't fill the {w}x{h} grid",
+                    points.len()
+                )))
+            }
+        }
+
+        // Rows run from the north.
+        let mut vals = vec![f32::NAN; w * h];
+        let mut filled = vec![false; w * h];
+        for &(x, y, z, n) in &points {
+            let column = ((x - left) / dx).round() as usize;
+            let row = ((top - y) / dy).round() as usize;
+            let i = row * w + column;
+            if filled[i] {
+                return Err(parse_error(n, format!("the point at {x}, {y} is repeated")));
+            }
+            filled[i] = true;
+            vals[i] = z;
+        }
+        Ok(Field::new((w as u32, h as u32), vals)?
+            .with_transform(GeoTransform::north_up((left, top), (dx, dy))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Point;
+
+    #[test]
+    fn ascii_grid_test() {
+        let asc = "ncols 3\n\
+                   nrows 2\n\
+                   xllcorner 1000\n\
+                   YLLCORNER 2000.0\n\
+                   cellsize 10\n\
+                   NODATA_value -9999\n\
+                   1 2.5 -3\n\
+                   4 -9999\n\
+                   6\n";
+        let field = Field::read_ascii_grid(asc.as_bytes()).unwrap();
+        assert_eq!((3, 2), field.extent);
+        assert_eq!(vec![1.0, 2.5, -3.0, 4.0, -9999.0, 6.0], field.vals);
+        assert_eq!(Some(-9999.0), field.nodata);
+        assert_eq!((1005.0, 2015.0), field.to_map(Point { x: 0.0, y: 0.0 }));
+        assert_eq!((1025.0, 2005.0), field.to_map(Point { x: 2.0, y: 1.0 }));
+
+        let centred = asc
+            .replace("xllcorner", "xllcenter")
+            .replace("YLLCORNER", "yllcenter");
+        let field = Field::read_ascii_grid(centred.as_bytes()).unwrap();
+        assert_eq!((1000.0, 2010.0), field.to_map(Point { x: 0.0, y: 0.0 }));
+    }
+
+    #[test]
+    fn ascii_grid_errors_test() {
+        let error = |asc: &str| {
+            Field::read_ascii_grid(asc.as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        let header = "ncols 2\nnrows 2\nxllcorner 0\nyllcorner 0\ncellsize 1\n";
+        assert_eq!(
+            "3 values don't fill the 2x2 grid of 4",
+            error(&format!("{header}1 2\n3\n"))
+        );
+        assert_eq!(
+            "line 7: \"x\" isn't a number",
+            error(&format!("{header}1 2\n3 x\n"))
+        );
+        assert_eq!(
+            "the header has no cellsize",
+            error("ncols 2\nnrows 2\nxllcorner 0\nyllcorner 0\n1 2 3 4\n")
+        );
+        assert_eq!(
+            "line 3: unknown header key \"xll\"",
+            error("ncols 2\nnrows 2\nxll 0\n")
+        );
+    }
+
+    #[test]
+    fn xyz_test() {
+        // Any order, with a header and rows running south.
+        let xyz = "x,y,z\n\
+                   10,200,1\n\
+                   20,200,2\n\
+                   30,200,3\n\
+                   30,210,6\n\
+                   20,210,5\n\
+                   10,210,4\n";
+        let field = Field::read_xyz(xyz.as_bytes()).unwrap();
+        assert_eq!((3, 2), field.extent);
+        assert_eq!(vec![4.0, 5.0, 6.0, 1.0, 2.0, 3.0], field.vals);
+        assert_eq!((10.0, 210.0), field.to_map(Point { x: 0.0, y: 0.0 }));
+        assert_eq!((30.0, 200.0), field.to_map(Point { x: 2.0, y: 1.0 }));
+
+        let error = |xyz: &str| Field::read_xyz(xyz.as_bytes()).unwrap_err().to_string();
+        assert_eq!(
+            "5 points don't fill the 3x2 grid of 6",
+            error(&xyz.replace("20,210,5\n", ""))
+        );
+        // A transect is rejected before room is made for the whole grid.
+        let diagonal: String = (0..200_000).map(|i| format!("{i} {i} 0\n")).collect();
+        assert_eq!(
+            "200000 points don't fill the 200000x200000 grid of 40000000000",
+            error(&diagonal)
+        );
+        assert_eq!(
+            "line 7: the point at 20, 210 is repeated",
+            error(&xyz.replace("10,210,4", "20,210,4"))
+        );
+        assert_eq!(
+            "the x coordinates aren't evenly spaced, 20 is off the grid of 12.5",
+            error(&xyz.replace("30,", "35,"))
+        );
+        assert_eq!(
+            "line 3: expected x, y and z but found 2 values",
+            error(&xyz.replace("20,200,2", "20 200"))
+        );
+    }
+}
@@ -0,0 +1,462 @@
+//! Filled bands between two thresholds.
+//!
+//! Every corner of a cell is classed as below, inside or above the band, which
+//! gives the 81 ternary cell configurations of marching squares isobands. Rather
+//! than spelling those out in a table, each cell walks its own boundary
+//! clockwise and joins up the crossings it meets, which covers the same cases
+//! including both kinds of saddle. Only the contour segments and the pieces of
+//! cell boundary that lie on the edge of the field are kept, and those are
+//! joined into rings through their shared grid vertices.
+
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, Result},
+    marching_squares::Field,
+    polygon::{polygons_from_rings, Polygon},
+    trace::side_origin,
+    util::*,
+};
+
+/// The polygons covering the values between two thresholds.
+#[derive(Debug, Clone)]
+pub struct ContourBand {
+    pub lower: f32,
+    pub upper: f32,
+    pub polygons: Vec<Polygon>,
+}
+
+// Where a ring vertex sits on the grid, which identifies it without comparing
+// coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Vertex {
+    Corner(u32, u32),
+    Crossing {
+        from: (u32, u32),
+        horizontal: bool,
+        upper: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StopKind {
+    Corner { inside: bool },
+    Crossing { upper: bool, enters: bool },
+}
+
+// A point met while walking clockwise around a cell.
+struct Stop {
+    vertex: Vertex,
+    point: RichPoint,
+    kind: StopKind,
+    // The side of the cell the walk arrives at this stop along
+    side: usize,
+}
+
+// A directed piece of a ring boundary, with the band on its right.
+struct RingEdge {
+    from: Vertex,
+    to: Vertex,
+    point: RichPoint,
+    cell: (u32, u32),
+}
+
+// Cell sides in clockwise order, side `k` runs from corner `k` to corner `k + 1`.
+const SIDES: [Edge; 4] = [Edge::Top, Edge::Right, Edge::Bottom, Edge::Left];
+
+struct BandTracer<'a> {
+    field: &'a Field,
+    lower: f32,
+    upper: f32,
+    edges: Vec<RingEdge>,
+}
+
+impl<'a> BandTracer<'a> {
+    fn class(&self, val: f32) -> u8 {
+        if val <= self.lower {
+            0
+        } else if val > self.upper {
+            2
+        } else {
+            1
+        }
+    }
+
+    // Whether the side of a cell is on the edge of the field or of a void.
+    fn on_border(&self, cell: (u32, u32), side: Edge) -> bool {
+        let (x, y) = cell;
+        let neighbour = match side {
+            Edge::Left if x > 0 => (x - 1, y),
+            Edge::Top if y > 0 => (x, y - 1),
+            Edge::Right if x + 2 < self.field.extent.0 => (x + 1, y),
+            Edge::Bottom if y + 2 < self.field.extent.1 => (x, y + 1),
+            _ => return true,
+        };
+        !self.field.valid_cell(neighbour)
+    }
+
+    fn crossing_stop(&self, cell: (u32, u32), side: usize, upper: bool, enters: bool) -> Stop {
+        let (from, horizontal) = side_origin(cell, SIDES[side]);
+        let threshold = if upper { self.upper } else { self.lower };
+        Stop {
+            vertex: Vertex::Crossing {
+                from,
+                horizontal,
+                upper,
+            },
+            point: self.field.crossing(from, horizontal, threshold),
+            kind: StopKind::Crossing { upper, enters },
+            side,
+        }
+    }
+
+    fn stops(&self, cell: (u32, u32)) -> Vec<Stop> {
+        let (x, y) = cell;
+        let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+        let classes = corners.map(|(x, y)| self.class(self.field.sample(x, y)));
+
+        let mut stops = Vec::new();
+        for k in 0..4 {
+            let (cx, cy) = corners[k];
+            let point = Point {
+                x: cx as f32,
+                y: cy as f32,
+            };
+            stops.push(Stop {
+                vertex: Vertex::Corner(cx, cy),
+                point: RichPoint {
+                    raw_point: point,
+                    interpolated_point: point,
+                },
+                kind: StopKind::Corner {
+                    inside: classes[k] == 1,
+                },
+                side: (k + 3) % 4,
+            });
+
+            let (a, b) = (classes[k], classes[(k + 1) % 4]);
+            if a < b {
+                if a == 0 {
+                    stops.push(self.crossing_stop(cell, k, false, true));
+                }
+                if b == 2 {
+                    stops.push(self.crossing_stop(cell, k, true, false));
+                }
+            } else if a > b {
+                if a == 2 {
+                    stops.push(self.crossing_stop(cell, k, true, true));
+                }
+                if b == 0 {
+                    stops.push(self.crossing_stop(cell, k, false, false));
+                }
+            }
+        }
+        stops
+    }
+
+    // The crossing that the contour leaving the band at `exit` comes back in
+    // through. Saddles are split by the average of the corners like `trace`.
+    fn partner(&self, stops: &[Stop], exit: usize, centre: f32) -> usize {
+        let StopKind::Crossing { upper, .. } = stops[exit].kind else {
+            unreachable!("Only crossings leave the band");
+        };
+        let level: Vec<usize> = (0..stops.len())
+            .filter(|&i| matches!(stops[i].kind, StopKind::Crossing { upper: u, .. } if u == upper))
+            .collect();
+        let at = level.iter().position(|&i| i == exit).unwrap();
+        let next = level[(at + 1) % level.len()];
+        if level.len() == 2 {
+            return next;
+        }
+
+        // When the band side of the saddle is joined through the centre the
+        // contours cut off the corners outside the band, which come straight
+        // after an exit in clockwise order.
+        let band_side_joined = if upper {
+            centre <= self.upper
+        } else {
+            centre > self.lower
+        };
+        if band_side_joined {
+            next
+        } else {
+            level[(at + level.len() - 1) % level.len()]
+        }
+    }
+
+    fn add_cell(&mut self, cell: (u32, u32)) {
+        if !self.field.valid_cell(cell) {
+            return;
+        }
+        let (x, y) = cell;
+        let class = self.class(self.field.sample(x, y));
+        let uniform = [(x + 1, y), (x, y + 1), (x + 1, y + 1)]
+            .iter()
+            .all(|&(x, y)| self.class(self.field.sample(x, y)) == class);
+        if uniform && (class != 1 || SIDES.iter().all(|&side| !self.on_border(cell, side))) {
+            // Nothing to contribute but shared cell sides.
+            return;
+        }
+
+        let stops = self.stops(cell);
+        let crossings = stops
+            .iter()
+            .filter(|stop| matches!(stop.kind, StopKind::Crossing { .. }))
+            .count();
+
+        if crossings == 0 {
+            // Entirely inside or entirely outside the band.
+            if stops[0].kind == (StopKind::Corner { inside: true }) {
+                for (k, stop) in stops.iter().enumerate() {
+                    let next = &stops[(k + 1) % 4];
+                    if self.on_border(cell, SIDES[next.side]) {
+                        self.edges.push(RingEdge {
+                            from: stop.vertex,
+                            to: next.vertex,
+                            point: stop.point.clone(),
+                            cell,
+                        });
+                    }
+                }
+            }
+            return;
+        }
+
+        let centre = (self.field.sample(x, y)
+            + self.field.sample(x + 1, y)
+            + self.field.sample(x, y + 1)
+            + self.field.sample(x + 1, y + 1))
+            / 4.0;
+
+        let mut done = vec![false; stops.len()];
+        for start in 0..stops.len() {
+            if done[start] || !matches!(stops[start].kind, StopKind::Crossing { enters: true, .. })
+            {
+                continue;
+            }
+            let mut at = start;
+            loop {
+                done[at] = true;
+                let next = (at + 1) % stops.len();
+                if self.on_border(cell, SIDES[stops[next].side]) {
+                    self.edges.push(RingEdge {
+                        from: stops[at].vertex,
+                        to: stops[next].vertex,
+                        point: stops[at].point.clone(),
+                        cell,
+                    });
+                }
+                at = next;
+                if let StopKind::Crossing { enters: false, .. } = stops[at].kind {
+                    done[at] = true;
+                    let partner = self.partner(&stops, at, centre);
+                    self.edges.push(RingEdge {
+                        from: stops[at].vertex,
+                        to: stops[partner].vertex,
+                        point: stops[at].point.clone(),
+                        cell,
+                    });
+                    at = partner;
+                }
+                if at == start {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn rings(self) -> Result<Vec<Path>> {
+        let mut outgoing = HashMap::with_capacity(self.edges.len());
+        // Where two cells with data only touch diagonally across voids, their
+        // shared corner has an edge leaving it in each of them.
+        let mut pinched = HashMap::new();
+        for (i, edge) in self.edges.iter().enumerate() {
+            if let Some(first) = outgoing.insert(edge.from, i) {
+                let corner = matches!(edge.from, Vertex::Corner(..));
+                if !corner || pinched.insert(edge.from, first).is_some() {
+                    return Err(Error::DegenerateTopology {
+                        point: edge.point.raw_point,
+                    });
+                }
+            }
+        }
+
+        let mut used = vec![false; self.edges.len()];
+        let mut rings = Vec::new();
+        for first in 0..self.edges.len() {
+            if used[first] {
+                continue;
+            }
+            let mut points = Vec::new();
+            let mut i = first;
+            while !used[i] {
+                used[i] = true;
+                let edge = &self.edges[i];
+                points.push(edge.point.clone());
+                let next = *outgoing.get(&edge.to).ok_or(Error::DegenerateTopology {
+                    point: edge.point.raw_point,
+                })?;
+                // Carry on around the same cell, like the contours do.
+                i = match pinched.get(&edge.to) {
+                    Some(&other) if self.edges[next].cell != edge.cell => other,
+                    _ => next,
+                };
+            }
+            points.push(self.edges[first].point.clone());
+            rings.push(Path {
+                points,
+                closed: true,
+            });
+        }
+        Ok(rings)
+    }
+}
+
+impl Field {
+    /// Polygons covering everywhere the field is above `lower` and no higher
+    /// than `upper`. Neighbouring bands share their boundaries exactly, so the
+    /// polygons of different bands never overlap. Cells touching missing data
+    /// are left out of every band.
+    pub fn band_polygons(&self, lower: f32, upper: f32) -> Result<Vec<Polygon>> {
+        if lower >= upper || lower.is_nan() || upper.is_nan() {
+            return Err(Error::InvalidBand { lower, upper });
+        }
+        let mut tracer = BandTracer {
+            field: self,
+            lower,
+            upper,
+            edges: Vec::new(),
+        };
+        for y in 0..self.extent.1.saturating_sub(1) {
+            for x in 0..self.extent.0.saturating_sub(1) {
+                tracer.add_cell((x, y));
+            }
+        }
+        Ok(polygons_from_rings(tracer.rings()?))
+    }
+
+    /// A band between each pair of consecutive thresholds, with the first one
+    /// covering everything up to the lowest threshold and the last one
+    /// everything above the highest, so that the bands tile the field.
+    pub fn bands(&self, thresholds: &[f32]) -> Result<Vec<ContourBand>> {
+        let limits: Vec<f32> = std::iter::once(f32::NEG_INFINITY)
+            .chain(thresholds.iter().copied())
+            .chain(std::iter::once(f32::INFINITY))
+            .collect();
+        limits
+            .windows(2)
+            .map(|limits| {
+                let (lower, upper) = (limits[0], limits[1]);
+                Ok(ContourBand {
+                    lower,
+                    upper,
+                    polygons: self.band_polygons(lower, upper)?,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area(polygons: &[Polygon]) -> f32 {
+        polygons
+            .iter()
+            .map(|p| {
+                p.exterior.signed_area(true)
+                    + p.holes.iter().map(|h| h.signed_area(true)).sum::<f32>()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn ring_with_hole_test() {
+        #[rustfmt::skip]
+        let field = Field::new((5, 5), vec![
+            0.0, 0.0, 0.0, 0.0, 0.0,
+            0.0, 4.0, 4.0, 4.0, 0.0,
+            0.0, 4.0, 8.0, 4.0, 0.0,
+            0.0, 4.0, 4.0, 4.0, 0.0,
+            0.0, 0.0, 0.0, 0.0, 0.0,
+        ]).unwrap();
+
+        let polygons = field.band_polygons(2.0, 6.0).unwrap();
+        assert_eq!(1, polygons.len());
+        assert_eq!(1, polygons[0].holes.len());
+        assert!(polygons[0].exterior.signed_area(true) > 0.0);
+        assert!(polygons[0].holes[0].signed_area(true) < 0.0);
+    }
+
+    #[test]
+    fn bands_tile_the_field_test() {
+        #[rustfmt::skip]
+        let field = Field::new((4, 4), vec![
+            0.0, 3.0, 6.0, 2.0,
+            7.0, 1.0, 8.0, 0.0,
+            2.0, 9.0, 0.0, 5.0,
+            4.0, 0.0, 6.0, 3.0,
+        ]).unwrap();
+
+        // Every band, including the ones with saddles, adds up to the whole
+        // 3x3 cell area with no overlap.
+        let total: f32 = field
+            .bands(&[-1.0, 2.5, 5.0, 7.5])
+            .unwrap()
+            .iter()
+            .map(|band| area(&band.polygons))
+            .sum();
+        assert!((total - 9.0).abs() < 1e-4, "{total}");
+    }
+
+    #[test]
+    fn lowest_band_test() {
+        // The floor at exactly the lowest threshold still belongs to a band.
+        #[rustfmt::skip]
+        let field = Field::new((3, 3), vec![
+            0.0, 0.0, 0.0,
+            0.0, 0.0, 4.0,
+            0.0, 4.0, 8.0,
+        ]).unwrap();
+
+        let bands = field.bands(&[0.0, 5.0]).unwrap();
+        assert_eq!(3, bands.len());
+        assert_eq!(f32::NEG_INFINITY, bands[0].lower);
+        assert_eq!(0.0, bands[0].upper);
+        assert!(area(&bands[0].polygons) > 0.0);
+        let total: f32 = bands.iter().map(|band| area(&band.polygons)).sum();
+        assert!((total - 4.0).abs() < 1e-4, "{total}");
+    }
+
+    #[test]
+    fn border_test() {
+        let field = Field::new((2, 2), vec![1.0, 1.0, 1.0, 1.0]).unwrap();
+        let polygons = field.band_polygons(0.0, 2.0).unwrap();
+        assert_eq!(1, polygons.len());
+        assert_eq!(5, polygons[0].exterior.points.len());
+        assert_eq!(1.0, polygons[0].exterior.signed_area(true));
+
+        assert!(field.band_polygons(2.0, 3.0).unwrap().is_empty());
+        assert!(matches!(
+            field.band_polygons(2.0, 2.0),
+            Err(Error::InvalidBand { .. })
+        ));
+    }
+
+    #[test]
+    fn void_test() {
+        // Only the two cells of data that touch diagonally are covered, and
+        // the corner they share doesn't confuse the rings.
+        #[rustfmt::skip]
+        let field = Field::new((3, 3), vec![
+            1.0, 1.0, -9.0,
+            1.0, 1.0, 1.0,
+            -9.0, 1.0, 1.0,
+        ]).unwrap().with_nodata(-9.0);
+
+        let polygons = field.band_polygons(0.0, 2.0).unwrap();
+        assert_eq!(2, polygons.len());
+        assert_eq!(2.0, area(&polygons));
+    }
+}
@@ -0,0 +1,426 @@
+//! Closing contours along the edge of the area that gets contoured, which is
+//! the border of the field less any cells that touch missing data.
+
+use std::collections::HashMap;
+
+use crate::{marching_squares::Field, util::*};
+
+// A corner of the sample grid.
+type Vertex = (u32, u32);
+
+// A grid edge, as the sample it leaves to the right or downwards and whether
+// it runs along x.
+type GridEdge = ((u32, u32), bool);
+
+fn direction(from: Vertex, to: Vertex) -> (i64, i64) {
+    (to.0 as i64 - from.0 as i64, to.1 as i64 - from.1 as i64)
+}
+
+fn vertex_point(vertex: Vertex) -> RichPoint {
+    let point = Point {
+        x: vertex.0 as f32,
+        y: vertex.1 as f32,
+    };
+    RichPoint {
+        raw_point: point,
+        interpolated_point: point,
+    }
+}
+
+/// The grid edges between contoured cells and cells that aren't, or the
+/// outside of the field. They're directed clockwise on screen around the
+/// contoured cells, keeping them on the right the same way contours keep the
+/// area above the threshold on the right.
+pub(crate) struct Boundary {
+    edges: Vec<(Vertex, Vertex)>,
+    by_grid_edge: HashMap<GridEdge, usize>,
+    // Two edges leave a vertex where contoured cells only touch diagonally.
+    outgoing: HashMap<Vertex, Vec<usize>>,
+}
+
+impl Boundary {
+    /// The boundary of the cells in a field of `extent` samples for which
+    /// `contoured` holds.
+    pub(crate) fn new(extent: (u32, u32), contoured: impl Fn((u32, u32)) -> bool) -> Self {
+        let mut boundary = Boundary {
+            edges: Vec::new(),
+            by_grid_edge: HashMap::new(),
+            outgoing: HashMap::new(),
+        };
+        if extent.0 < 2 || extent.1 < 2 {
+            return boundary;
+        }
+
+        let cells = (extent.0 - 1, extent.1 - 1);
+        let mut valid = Vec::with_capacity(cells.0 as usize * cells.1 as usize);
+        for y in 0..cells.1 {
+            for x in 0..cells.0 {
+                valid.push(contoured((x, y)));
+            }
+        }
+        let is_valid = |x: i64, y: i64| {
+            x >= 0
+                && y >= 0
+                && x < cells.0 as i64
+                && y < cells.1 as i64
+                && valid[x as usize + y as usize * cells.0 as usize]
+        };
+
+        for y in 0..cells.1 {
+            for x in 0..cells.0 {
+                let (xi, yi) = (x as i64, y as i64);
+                if !is_valid(xi, yi) {
+                    continue;
+                }
+                if !is_valid(xi, yi - 1) {
+                    boundary.add((x, y), (x + 1, y));
+                }
+                if !is_valid(xi + 1, yi) {
+                    boundary.add((x + 1, y), (x + 1, y + 1));
+                }
+                if !is_valid(xi, yi + 1) {
+                    boundary.add((x + 1, y + 1), (x, y + 1));
+                }
+                if !is_valid(xi - 1, yi) {
+                    boundary.add((x, y + 1), (x, y));
+                }
+            }
+        }
+        boundary
+    }
+
+    /// The boundary of a field of `extent` samples where every cell is
+    /// contoured, which is just its border.
+    pub(crate) fn around(extent: (u32, u32)) -> Self {
+        let mut boundary = Boundary {
+            edges: Vec::new(),
+            by_grid_edge: HashMap::new(),
+            outgoing: HashMap::new(),
+        };
+        if extent.0 < 2 || extent.1 < 2 {
+            return boundary;
+        }
+        let (w, h) = (extent.0 - 1, extent.1 - 1);
+        for x in 0..w {
+            boundary.add((x, 0), (x + 1, 0));
+        }
+        for y in 0..h {
+            boundary.add((w, y), (w, y + 1));
+        }
+        for x in (0..w).rev() {
+            boundary.add((x + 1, h), (x, h));
+        }
+        for y in (0..h).rev() {
+            boundary.add((0, y + 1), (0, y));
+        }
+        boundary
+    }
+
+    fn add(&mut self, from: Vertex, to: Vertex) {
+        let i = self.edges.len();
+        self.edges.push((from, to));
+        self.by_grid_edge.insert((from.min(to), from.1 == to.1), i);
+        self.outgoing.entry(from).or_default().push(i);
+    }
+
+    fn direction(&self, edge: usize) -> (i64, i64) {
+        let (from, to) = self.edges[edge];
+        direction(from, to)
+    }
+
+    fn next(&self, edge: usize) -> usize {
+        let outgoing = &self.outgoing[&self.edges[edge].1];
+        if let [next] = outgoing[..] {
+            return next;
+        }
+        // Turning right stays on the same cell, which keeps cells that only
+        // share a corner apart like the tracer does.
+        let (dx, dy) = self.direction(edge);
+        *outgoing
+            .iter()
+            .find(|&&next| self.direction(next) == (-dy, dx))
+            .expect("Pinched vertices have a right turn")
+    }
+
+    // The boundary edge a path endpoint lies on, if any.
+    fn edge_at(&self, point: &RichPoint) -> Option<usize> {
+        let Point { x, y } = point.raw_point;
+        let key = if x.fract() == 0.5 {
+            ((x.floor() as u32, y as u32), true)
+        } else if y.fract() == 0.5 {
+            ((x as u32, y.floor() as u32), false)
+        } else {
+            return None;
+        };
+        self.by_grid_edge.get(&key).copied()
+    }
+
+    /// Closes open paths by following the boundary from where each one leaves
+    /// to where the next one comes back in. Paths are chained into a single
+    /// ring when the region they bound touches the boundary more than once,
+    /// and the ring takes the place of the first of them. Paths that don't
+    /// start and end on the boundary are left open.
+    ///
+    /// Loops of the boundary that no path reaches are added as rings of their
+    /// own when `above` holds for their vertices.
+    pub(crate) fn close_paths(&self, paths: &mut Vec<Path>, above: impl Fn(Vertex) -> bool) {
+        let mut visited = vec![false; self.edges.len()];
+        let mut starts: HashMap<usize, usize> = HashMap::new();
+        for (i, path) in paths.iter().enumerate() {
+            if path.closed || path.points.is_empty() {
+                continue;
+            }
+            if let Some(edge) = self.edge_at(&path.start()) {
+                starts.insert(edge, i);
+                visited[edge] = true;
+            }
+        }
+
+        // The path that comes back in after each one leaves, and the corners
+        // of the boundary on the way.
+        let mut next: Vec<Option<(usize, Vec<RichPoint>)>> = vec![None; paths.len()];
+        for (i, path) in paths.iter().enumerate() {
+            if path.closed || path.points.is_empty() {
+                continue;
+            }
+            let Some(leave) = self.edge_at(&path.end()) else {
+                continue;
+            };
+            visited[leave] = true;
+            let mut corners = Vec::new();
+            let mut edge = leave;
+            loop {
+                let following = self.next(edge);
+                if self.direction(following) != self.direction(edge) {
+                    corners.push(vertex_point(self.edges[edge].1));
+                }
+                if let Some(&j) = starts.get(&following) {
+                    next[i] = Some((j, corners));
+                    break;
+                }
+                // A boundary loop with nowhere to come back in means the
+                // paths don't agree on which side is above.
+                if visited[following] {
+                    break;
+                }
+                visited[following] = true;
+                edge = following;
+            }
+        }
+
+        let mut taken = vec![false; paths.len()];
+        let mut rings: Vec<(usize, Path)> = Vec::new();
+        for first in 0..paths.len() {
+            if taken[first] || next[first].is_none() {
+                continue;
+            }
+            let mut chain = vec![first];
+            let mut current = first;
+            let closes = loop {
+                match &next[current] {
+                    Some((following, _)) if *following == first => break true,
+                    Some((following, _)) if !taken[*following] && !chain.contains(following) => {
+                        chain.push(*following);
+                        current = *following;
+                    }
+                    _ => break false,
+                }
+            };
+            if !closes {
+                continue;
+            }
+
+            let mut points = Vec::new();
+            for &i in &chain {
+                taken[i] = true;
+                points.extend(paths[i].points.iter().cloned());
+                let (_, corners) = next[i].as_ref().expect("Chained paths have a successor");
+                points.extend(corners.iter().cloned());
+            }
+            points.push(points[0].clone());
+            rings.push((
+                first,
+                Path {
+                    points,
+                    closed: true,
+                },
+            ));
+        }
+
+        for (i, ring) in rings {
+            paths[i] = ring;
+        }
+        let mut i = 0;
+        paths.retain(|path| {
+            let keep = path.closed || !taken[i];
+            i += 1;
+            keep
+        });
+
+        for first in 0..self.edges.len() {
+            if visited[first] {
+                continue;
+            }
+            let mut edges = vec![first];
+            let mut edge = self.next(first);
+            let mut untouched = true;
+            while edge != first {
+                untouched &= !visited[edge];
+                edges.push(edge);
+                edge = self.next(edge);
+            }
+            for &edge in &edges {
+                visited[edge] = true;
+            }
+            if !untouched || !above(self.edges[first].0) {
+                continue;
+            }
+
+            let mut points: Vec<RichPoint> = edges
+                .iter()
+                .zip(edges.iter().cycle().skip(edges.len() - 1))
+                .filter(|(&edge, &previous)| self.direction(edge) != self.direction(previous))
+                .map(|(&edge, _)| vertex_point(self.edges[edge].0))
+                .collect();
+            points.push(points[0].clone());
+            paths.push(Path {
+                points,
+                closed: true,
+            });
+        }
+    }
+}
+
+impl Field {
+    /// The boundary of the cells that have data on all four corners.
+    pub(crate) fn boundary(&self) -> Boundary {
+        if self.vals.iter().all(|&val| self.is_valid(val)) {
+            Boundary::around(self.extent)
+        } else {
+            Boundary::new(self.extent, |pos| self.valid_cell(pos))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn xs(path: &Path) -> Vec<(f32, f32)> {
+        path.points
+            .iter()
+            .map(|p| (p.raw_point.x, p.raw_point.y))
+            .collect()
+    }
+
+    #[test]
+    fn close_edges_test() {
+        // Every pair of edges a path can start and end on in a 5x5 field. The
+        // border is followed clockwise from the end back to the start, so
+        // paths on a single edge either close directly or go all the way round.
+        let (tl, tr, br, bl) = ((0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0));
+        let (top, top2) = ((1.5, 0.0), (2.5, 0.0));
+        let (right, right2) = ((4.0, 1.5), (4.0, 2.5));
+        let (bottom, bottom2) = ((2.5, 4.0), (1.5, 4.0));
+        let (left, left2) = ((0.0, 2.5), (0.0, 1.5));
+        #[rustfmt::skip]
+        let cases = [
+            (top, top2, vec![tr, br, bl, tl]),
+            (top2, top, vec![]),
+            (top, right, vec![br, bl, tl]),
+            (top, bottom, vec![bl, tl]),
+            (top, left, vec![tl]),
+            (right, top, vec![tr]),
+            (right, right2, vec![br, bl, tl, tr]),
+            (right2, right, vec![]),
+            (right, bottom, vec![bl, tl, tr]),
+            (right, left, vec![tl, tr]),
+            (bottom, top, vec![tr, br]),
+            (bottom, right, vec![br]),
+            (bottom, bottom2, vec![bl, tl, tr, br]),
+            (bottom2, bottom, vec![]),
+            (bottom, left, vec![tl, tr, br]),
+            (left, top, vec![tr, br, bl]),
+            (left, right, vec![br, bl]),
+            (left, bottom, vec![bl]),
+            (left, left2, vec![tl, tr, br, bl]),
+            (left2, left, vec![]),
+        ];
+
+        for (start, end, corners) in cases {
+            let mut paths = vec![Path::from_xy(&[start, (2.0, 2.0), end], false)];
+            Boundary::around((5, 5)).close_paths(&mut paths, |_| false);
+
+            let mut expected = vec![start, (2.0, 2.0), end];
+            expected.extend(corners);
+            expected.push(start);
+            assert_eq!(1, paths.len());
+            assert!(paths[0].closed);
+            assert_eq!(expected, xs(&paths[0]), "from {start:?} to {end:?}");
+        }
+    }
+
+    #[test]
+    fn chain_edges_test() {
+        // A ridge across the field is bounded by two paths, one along each
+        // side, which close into a single ring.
+        let mut paths = vec![
+            Path::from_xy(&[(0.0, 1.5), (4.0, 1.5)], false),
+            Path::from_xy(&[(4.0, 2.5), (0.0, 2.5)], false),
+            Path::from_xy(&[(1.5, 0.0), (1.5, 0.5)], false),
+        ];
+        Boundary::around((5, 5)).close_paths(&mut paths, |_| false);
+
+        assert_eq!(2, paths.len());
+        assert!(paths[0].closed);
+        assert_eq!(
+            vec![(0.0, 1.5), (4.0, 1.5), (4.0, 2.5), (0.0, 2.5), (0.0, 1.5)],
+            xs(&paths[0])
+        );
+        assert!(!paths[1].closed);
+    }
+
+    #[test]
+    fn void_boundary_test() {
+        // A void in the middle of a plateau is a hole in its ring.
+        #[rustfmt::skip]
+        let field = Field::new((5, 5), vec![
+            9.0, 9.0, 9.0, 9.0, 9.0,
+            9.0, 9.0, 9.0, 9.0, 9.0,
+            9.0, 9.0, f32::NAN, 9.0, 9.0,
+            9.0, 9.0, 9.0, 9.0, 9.0,
+            9.0, 9.0, 9.0, 9.0, 9.0,
+        ]).unwrap();
+
+        let paths = field.layer_paths(5.0, true, Mode::Strict).unwrap().0;
+        assert_eq!(2, paths.len());
+        assert_eq!(
+            vec![(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0), (0.0, 0.0)],
+            xs(&paths[0])
+        );
+        assert_eq!(-4.0, paths[1].signed_area(true));
+        assert!(field
+            .layer_paths(10.0, true, Mode::Strict)
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn pinch_test() {
+        // Cells with data that only touch at a corner are closed separately.
+        let valid = |pos| pos == (0, 0) || pos == (1, 1);
+        let mut paths = vec![];
+        Boundary::new((3, 3), valid).close_paths(&mut paths, |_| true);
+
+        assert_eq!(2, paths.len());
+        assert_eq!(
+            vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)],
+            xs(&paths[0])
+        );
+        assert_eq!(
+            vec![(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 2.0), (1.0, 1.0)],
+            xs(&paths[1])
+        );
+    }
+}
@@ -0,0 +1,347 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use isolines::{
+    plot::{Paper, PlotFormat},
+    rgb::RgbEncoding,
+    simplify::Method,
+    smooth,
+};
+
+// Flags that only apply to contour lines, which the band and polygon output
+// rejects rather than ignores.
+const LINES_ONLY: [&str; 8] = [
+    "no_close_edges",
+    "strict",
+    "min_area",
+    "min_length",
+    "min_size",
+    "simplify",
+    "simplify_method",
+    "smooth",
+];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "isolines",
+    version,
+    about = "Contour lines from elevation rasters"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Render contours of a DEM as an SVG document
+    Svg(SvgArgs),
+    /// Write contours of a DEM as GeoJSON, in map units when it's georeferenced
+    Geojson(GeoJsonArgs),
+    /// Write contours of a DEM as an ESRI shapefile
+    Shapefile(ShapefileArgs),
+    /// Write contours of a DEM as an R12 DXF drawing, a layer per level
+    Dxf(DxfArgs),
+    /// Write contours of a DEM as HPGL or G-code for a pen plotter
+    Plot(PlotArgs),
+    /// Print the contour levels that would be used for a DEM
+    Levels(ContourArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct SvgArgs {
+    #[command(flatten)]
+    pub contour: ContourArgs,
+
+    #[command(flatten)]
+    pub post_process: PostProcessArgs,
+
+    /// Where to write the SVG, defaults to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// File with one colour per line, lowest level last
+    #[arg(short, long)]
+    pub palette: Option<PathBuf>,
+
+    /// Use the raw cell-midpoint geometry instead of interpolating along cell edges
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Write coordinates in the raster's map units instead of pixels
+    #[arg(long)]
+    pub map_units: bool,
+
+    /// Fill the bands between levels instead of drawing contour lines
+    #[arg(long, conflicts_with_all = LINES_ONLY)]
+    pub bands: bool,
+
+    /// Leave contours that run off the edge of the raster open
+    #[arg(long)]
+    pub no_close_edges: bool,
+
+    /// Draw contours as cubic Bézier curves through their points
+    #[arg(long)]
+    pub curves: bool,
+
+    /// Stroke width of index contours as a multiple of the others
+    #[arg(long, default_value_t = 2.0)]
+    pub index_width: f64,
+
+    /// Label contours with their elevation
+    #[arg(long, conflicts_with = "bands")]
+    pub labels: bool,
+
+    /// Height of the label text in pixels
+    #[arg(long, default_value_t = 12.0, requires = "labels")]
+    pub label_size: f32,
+
+    /// Shortest distance along a contour between two of its labels, in pixels
+    #[arg(long, default_value_t = 400.0, requires = "labels")]
+    pub label_spacing: f32,
+
+    /// Decimal places of the label elevations, defaults to the fewest that
+    /// tell the levels apart
+    #[arg(long, requires = "labels")]
+    pub label_decimals: Option<usize>,
+}
+
+#[derive(Debug, Args)]
+pub struct GeoJsonArgs {
+    #[command(flatten)]
+    pub contour: ContourArgs,
+
+    #[command(flatten)]
+    pub post_process: PostProcessArgs,
+
+    /// Where to write the GeoJSON, defaults to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Use the raw cell-midpoint geometry instead of interpolating along cell edges
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Write the polygons between levels instead of contour lines
+    #[arg(long, conflicts_with_all = LINES_ONLY)]
+    pub bands: bool,
+
+    /// Leave contours that run off the edge of the raster open
+    #[arg(long)]
+    pub no_close_edges: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ShapefileArgs {
+    #[command(flatten)]
+    pub contour: ContourArgs,
+
+    #[command(flatten)]
+    pub post_process: PostProcessArgs,
+
+    /// Path of the .shp file, the .shx, .dbf and .prj files are written next to it
+    #[arg(short, long)]
+    pub output: PathBuf,
+
+    /// Use the raw cell-midpoint geometry instead of interpolating along cell edges
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Write the polygons between levels instead of contour lines
+    #[arg(long, conflicts_with_all = LINES_ONLY)]
+    pub polygons: bool,
+
+    /// Leave contours that run off the edge of the raster open
+    #[arg(long)]
+    pub no_close_edges: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DxfArgs {
+    #[command(flatten)]
+    pub contour: ContourArgs,
+
+    #[command(flatten)]
+    pub post_process: PostProcessArgs,
+
+    /// Where to write the DXF, defaults to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Use the raw cell-midpoint geometry instead of interpolating along cell edges
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Polyline width of index contours in drawing units, defaults to one pixel
+    #[arg(long)]
+    pub index_width: Option<f64>,
+
+    /// Leave contours that run off the edge of the raster open
+    #[arg(long)]
+    pub no_close_edges: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PlotArgs {
+    #[command(flatten)]
+    pub contour: ContourArgs,
+
+    #[command(flatten)]
+    pub post_process: PostProcessArgs,
+
+    /// Where to write the plot, defaults to stdout
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Plotter language, hpgl or gcode
+    #[arg(short, long, default_value = "hpgl")]
+    pub format: PlotFormat,
+
+    /// File with one colour per line, lowest level last. Each colour gets its own pen.
+    #[arg(short, long)]
+    pub palette: Option<PathBuf>,
+
+    /// Use the raw cell-midpoint geometry instead of interpolating along cell edges
+    #[arg(long)]
+    pub raw: bool,
+
+    /// Paper size, a3, a4, a5 or letter with an optional -landscape, or WIDTHxHEIGHT in mm
+    #[arg(long, default_value = "a4")]
+    pub paper: Paper,
+
+    /// Blank space around the edges of the paper in mm
+    #[arg(long, default_value_t = 10.0)]
+    pub margin: f64,
+
+    /// Millimetres per pixel, defaults to filling the paper
+    #[arg(long)]
+    pub scale: Option<f64>,
+
+    /// Drawing speed in mm per minute
+    #[arg(long, default_value_t = 1500.0)]
+    pub feed_rate: f64,
+
+    /// Draw paths in the order they're traced instead of shortening the travel between them
+    #[arg(long)]
+    pub keep_order: bool,
+
+    /// Draw open contours in the direction they're traced instead of
+    /// reversing them where that shortens the travel
+    #[arg(long, conflicts_with = "keep_order")]
+    pub no_reverse: bool,
+
+    /// G-code command that lifts the pen
+    #[arg(long, default_value = "G0 Z5", allow_hyphen_values = true)]
+    pub pen_up: String,
+
+    /// G-code command that lowers the pen
+    #[arg(long, default_value = "G0 Z0", allow_hyphen_values = true)]
+    pub pen_down: String,
+
+    /// Leave contours that run off the edge of the raster open
+    #[arg(long)]
+    pub no_close_edges: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ContourArgs {
+    /// Elevation raster to contour
+    pub input: PathBuf,
+
+    /// Decode elevations from the colours of a web map tile, encoded as
+    /// terrarium or terrain-rgb
+    #[arg(short, long)]
+    pub encoding: Option<RgbEncoding>,
+
+    /// Georeference a web map tile in Web Mercator from its position, as Z/X/Y
+    #[arg(long, value_delimiter = '/', value_name = "Z/X/Y")]
+    pub tile: Option<Vec<u32>>,
+
+    /// Distance between contour levels
+    #[arg(short, long, conflicts_with = "levels")]
+    pub interval: Option<f32>,
+
+    /// Explicit comma separated list of contour levels
+    #[arg(short, long, value_delimiter = ',', allow_negative_numbers = true)]
+    pub levels: Option<Vec<f32>>,
+
+    /// Elevation that the contour interval is anchored to
+    #[arg(short, long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pub base: f32,
+
+    /// Make every this many levels an index contour, counting multiples of
+    /// the interval from the base, or levels from the lowest without one
+    #[arg(long)]
+    pub index_every: Option<usize>,
+
+    /// Value marking samples with no data, which are left out of the contours
+    #[arg(short, long, allow_negative_numbers = true)]
+    pub nodata: Option<f32>,
+
+    /// Median filter the raster over squares reaching this many pixels out,
+    /// before any other filter
+    #[arg(long)]
+    pub median: Option<usize>,
+
+    /// Bilateral filter the raster with a spatial sigma in pixels and a sigma
+    /// in elevation, as SPACE,RANGE
+    #[arg(long, value_delimiter = ',', value_name = "SPACE,RANGE")]
+    pub bilateral: Option<Vec<f32>>,
+
+    /// Gaussian blur the raster with this sigma in pixels, after any other filter
+    #[arg(long)]
+    pub gaussian: Option<f32>,
+
+    /// Fail on ambiguous contour topology instead of warning and carrying on
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Number of levels to use when neither an interval nor a list is given,
+    /// defaults to the size of the palette
+    #[arg(short, long, conflicts_with_all = ["interval", "levels"])]
+    pub count: Option<usize>,
+
+    /// Use this many levels splitting the raster into areas of the same size
+    #[arg(long, conflicts_with_all = ["interval", "levels", "count", "logarithmic"])]
+    pub quantiles: Option<usize>,
+
+    /// Use this many levels spaced logarithmically, closer together low down
+    #[arg(long, conflicts_with_all = ["interval", "levels", "count"])]
+    pub logarithmic: Option<usize>,
+}
+
+/// Clean-up of the traced contours, for the subcommands that write them.
+#[derive(Debug, Args)]
+pub struct PostProcessArgs {
+    /// Drop closed rings enclosing less than this many square pixels
+    #[arg(long, default_value_t = 0.0)]
+    pub min_area: f32,
+
+    /// Drop rings with a shorter perimeter and open lines shorter than this, in pixels
+    #[arg(long, default_value_t = 0.0)]
+    pub min_length: f32,
+
+    /// Drop contours whose bounding box is smaller than this on its longer side, in pixels
+    #[arg(long, default_value_t = 0.0)]
+    pub min_size: f32,
+
+    /// Simplify contours, moving them by at most this many pixels
+    #[arg(long)]
+    pub simplify: Option<f64>,
+
+    /// Take the simplification tolerance in the raster's map units
+    #[arg(long, requires = "simplify")]
+    pub simplify_map_units: bool,
+
+    /// Simplification algorithm, dp for Douglas-Peucker or vw for Visvalingam-Whyatt
+    #[arg(long, default_value = "dp")]
+    pub simplify_method: Method,
+
+    /// Smooth contours, after any simplification, with chaikin or catmull-rom
+    #[arg(long)]
+    pub smooth: Option<smooth::Method>,
+
+    /// Rounds of Chaikin corner cutting, or Catmull-Rom segments between points
+    #[arg(long, default_value_t = 3, requires = "smooth")]
+    pub smooth_steps: usize,
+}
@@ -0,0 +1,237 @@
+//! DXF output for CAD, as an AutoCAD R12 (AC1009) ASCII drawing with the
+//! contours of each level on a layer of their own.
+
+use std::io::{self, Write};
+
+use crate::{
+    geo::{GeoTransform, Geometry},
+    marching_squares::{ContourKind, ContourLevel},
+};
+
+/// Layer shared by the index contours of every level.
+pub const INDEX_LAYER: &str = "CONTOUR_INDEX";
+
+#[derive(Debug, Clone, Default)]
+pub struct DxfOptions {
+    pub geometry: Geometry,
+    /// Polyline width of index contours in drawing units, one pixel if unset.
+    /// R12 has no lineweights, so the width is what makes them heavier.
+    pub index_width: Option<f64>,
+}
+
+impl DxfOptions {
+    fn index_width(&self) -> f64 {
+        self.index_width
+            .unwrap_or_else(|| self.geometry.pixel_size())
+    }
+}
+
+/// Name of the layer an intermediate level goes on. R12 layer names can't
+/// contain dots, so decimal places are separated by an underscore.
+pub fn layer_name(elevation: f32) -> String {
+    format!("CONTOUR_{elevation}").replace('.', "_")
+}
+
+fn layer(level: &ContourLevel) -> String {
+    match level.kind {
+        Some(ContourKind::Index) => INDEX_LAYER.to_owned(),
+        _ => layer_name(level.elevation),
+    }
+}
+
+fn group<W: Write>(out: &mut W, code: u16, value: impl std::fmt::Display) -> io::Result<()> {
+    write!(out, "{code:>3}\n{value}\n")
+}
+
+/// Writes every path with at least two points as a 2D POLYLINE at the
+/// elevation of its level. Closed paths are flagged as closed instead of
+/// repeating their first vertex. Without a transform the rows of the field
+/// are flipped, since y runs up in DXF.
+pub fn write_dxf<W: Write>(
+    out: &mut W,
+    extent: (u32, u32),
+    levels: &[ContourLevel],
+    options: &DxfOptions,
+) -> io::Result<()> {
+    let geometry = Geometry {
+        transform: Some(options.geometry.transform.unwrap_or_else(|| {
+            GeoTransform::north_up((0.0, extent.1.saturating_sub(1) as f64), (1.0, 1.0))
+        })),
+        ..options.geometry
+    };
+    let mut layers: Vec<String> = Vec::new();
+    for level in levels {
+        let name = layer(level);
+        if !layers.contains(&name) {
+            layers.push(name);
+        }
+    }
+    let (min, max) = levels
+        .iter()
+        .flat_map(|level| &level.paths)
+        .flat_map(|path| &path.points)
+        .map(|p| geometry.point(p))
+        .fold(None, |bounds, (x, y)| match bounds {
+            None => Some(((x, y), (x, y))),
+            Some((min, max)) => Some(((x.min(min.0), y.min(min.1)), (x.max(max.0), y.max(max.1)))),
+        })
+        .unwrap_or(((0.0, 0.0), (0.0, 0.0)));
+    let (low, high) = levels
+        .iter()
+        .map(|level| (level.elevation, level.elevation))
+        .reduce(|(lo, hi), (low, high)| (lo.min(low), hi.max(high)))
+        .unwrap_or((0.0, 0.0));
+
+    group(out, 0, "SECTION")?;
+    group(out, 2, "HEADER")?;
+    group(out, 9, "$ACADVER")?;
+    group(out, 1, "AC1009")?;
+    group(out, 9, "$EXTMIN")?;
+    group(out, 10, min.0)?;
+    group(out, 20, min.1)?;
+    group(out, 30, low)?;
+    group(out, 9, "$EXTMAX")?;
+    group(out, 10, max.0)?;
+    group(out, 20, max.1)?;
+    group(out, 30, high)?;
+    group(out, 0, "ENDSEC")?;
+
+    group(out, 0, "SECTION")?;
+    group(out, 2, "TABLES")?;
+    group(out, 0, "TABLE")?;
+    group(out, 2, "LAYER")?;
+    group(out, 70, layers.len())?;
+    for name in &layers {
+        group(out, 0, "LAYER")?;
+        group(out, 2, name)?;
+        group(out, 70, 0)?;
+        group(out, 62, 7)?;
+        group(out, 6, "CONTINUOUS")?;
+    }
+    group(out, 0, "ENDTAB")?;
+    group(out, 0, "ENDSEC")?;
+
+    group(out, 0, "SECTION")?;
+    group(out, 2, "ENTITIES")?;
+    for level in levels {
+        let name = layer(level);
+        for path in level.paths.iter().filter(|p| p.points.len() > 1) {
+            group(out, 0, "POLYLINE")?;
+            group(out, 8, &name)?;
+            group(out, 66, 1)?;
+            group(out, 10, 0.0)?;
+            group(out, 20, 0.0)?;
+            group(out, 30, level.elevation)?;
+            group(out, 70, if path.closed { 1 } else { 0 })?;
+            if level.kind == Some(ContourKind::Index) {
+                group(out, 40, options.index_width())?;
+                group(out, 41, options.index_width())?;
+            }
+
+            let points = if path.closed {
+                &path.points[..path.points.len() - 1]
+            } else {
+                &path.points[..]
+            };
+            for point in points {
+                let (x, y) = geometry.point(point);
+                group(out, 0, "VERTEX")?;
+                group(out, 8, &name)?;
+                group(out, 10, x)?;
+                group(out, 20, y)?;
+                group(out, 30, 0.0)?;
+            }
+            group(out, 0, "SEQEND")?;
+            group(out, 8, &name)?;
+        }
+    }
+    group(out, 0, "ENDSEC")?;
+    group(out, 0, "EOF")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Path;
+
+    #[test]
+    fn layer_name_test() {
+        assert_eq!("CONTOUR_100", layer_name(100.0));
+        assert_eq!("CONTOUR_-2_5", layer_name(-2.5));
+    }
+
+    #[test]
+    fn dxf_test() {
+        let levels = [
+            ContourLevel {
+                elevation: 10.0,
+                paths: vec![Path::from_xy(&[(0.0, 0.0), (1.0, 0.0)], false)],
+                kind: None,
+            },
+            ContourLevel {
+                elevation: 20.5,
+                paths: vec![Path::from_xy(
+                    &[(0.0, 0.0), (2.0, 0.0), (0.0, 2.0), (0.0, 0.0)],
+                    true,
+                )],
+                kind: Some(ContourKind::Index),
+            },
+        ];
+        let options = DxfOptions {
+            index_width: Some(0.5),
+            ..Default::default()
+        };
+
+        let mut out = Vec::new();
+        write_dxf(&mut out, (3, 3), &levels, &options).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            "  0", "SECTION", "  2", "HEADER",
+            "  9", "$ACADVER", "  1", "AC1009",
+            "  9", "$EXTMIN", " 10", "0", " 20", "0", " 30", "10",
+            "  9", "$EXTMAX", " 10", "2", " 20", "2", " 30", "20.5",
+            "  0", "ENDSEC",
+            "  0", "SECTION", "  2", "TABLES",
+            "  0", "TABLE", "  2", "LAYER", " 70", "2",
+            "  0", "LAYER", "  2", "CONTOUR_10", " 70", "0", " 62", "7", "  6", "CONTINUOUS",
+            "  0", "LAYER", "  2", "CONTOUR_INDEX", " 70", "0", " 62", "7", "  6", "CONTINUOUS",
+            "  0", "ENDTAB", "  0", "ENDSEC",
+            "  0", "SECTION", "  2", "ENTITIES",
+            "  0", "POLYLINE", "  8", "CONTOUR_10", " 66", "1",
+            " 10", "0", " 20", "0", " 30", "10", " 70", "0",
+            "  0", "VERTEX", "  8", "CONTOUR_10", " 10", "0", " 20", "2", " 30", "0",
+            "  0", "VERTEX", "  8", "CONTOUR_10", " 10", "1", " 20", "2", " 30", "0",
+            "  0", "SEQEND", "  8", "CONTOUR_10",
+            "  0", "POLYLINE", "  8", "CONTOUR_INDEX", " 66", "1",
+            " 10", "0", " 20", "0", " 30", "20.5", " 70", "1", " 40", "0.5", " 41", "0.5",
+            "  0", "VERTEX", "  8", "CONTOUR_INDEX", " 10", "0", " 20", "2", " 30", "0",
+            "  0", "VERTEX", "  8", "CONTOUR_INDEX", " 10", "2", " 20", "2", " 30", "0",
+            "  0", "VERTEX", "  8", "CONTOUR_INDEX", " 10", "0", " 20", "0", " 30", "0",
+            "  0", "SEQEND", "  8", "CONTOUR_INDEX",
+            "  0", "ENDSEC", "  0", "EOF",
+        ];
+        assert_eq!(expected.join("\n") + "\n", String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn north_up_test() {
+        // A field traced with no georeferencing has its top row, y = 0 in
+        // pixels, at the top of the drawing.
+        let levels = [ContourLevel {
+            elevation: 1.0,
+            paths: vec![Path::from_xy(&[(1.0, 0.0), (1.0, 4.0)], false)],
+            kind: None,
+        }];
+        let mut out = Vec::new();
+        write_dxf(&mut out, (3, 5), &levels, &DxfOptions::default()).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        let ys: Vec<f64> = lines
+            .windows(2)
+            .skip_while(|w| w[1] != "VERTEX")
+            .filter(|w| w[0] == " 20")
+            .map(|w| w[1].parse().unwrap())
+            .collect();
+        assert_eq!(vec![4.0, 0.0], ys);
+    }
+}
@@ -0,0 +1,132 @@
+use std::{fmt, io};
+
+use image::ImageError;
+use tiff::TiffError;
+
+use crate::util::Point;
+
+#[derive(Debug)]
+pub enum Error {
+    /// More than one path fragment starts or ends at the same point, so there
+    /// is no single way to stitch them together.
+    DegenerateTopology { point: Point },
+    /// A sample or cell position that lies outside the field.
+    InvalidCoordinates { pos: (u32, u32), extent: (u32, u32) },
+    /// The number of values doesn't fill the extent of the field.
+    InvalidExtent { extent: (u32, u32), len: usize },
+    /// The lower threshold of a band isn't below the upper one.
+    InvalidBand { lower: f32, upper: f32 },
+    /// The contour interval isn't positive.
+    InvalidInterval { interval: f32 },
+    /// No levels were asked for.
+    InvalidLevelCount,
+    /// The raster could not be read or decoded.
+    InvalidImage(ImageError),
+    /// The TIFF could not be decoded.
+    InvalidTiff(TiffError),
+    /// A text raster is malformed, at the given line counting from 1 if
+    /// the problem is with one line.
+    Parse {
+        line: Option<usize>,
+        message: String,
+    },
+    /// The file could not be read.
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An anomaly in the contour topology that lenient mode worked around.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warning {
+    /// Several path fragments meet at the point, so they were left unjoined.
+    Unjoined { point: Point },
+    /// A contour had nowhere to go from the point, so it was ended there.
+    DeadEnd { point: Point },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DegenerateTopology { point } => write!(
+                f,
+                "ambiguous contour topology, several paths meet at ({}, {})",
+                point.x, point.y
+            ),
+            Error::InvalidCoordinates { pos, extent } => write!(
+                f,
+                "coordinates ({}, {}) are outside of the {}x{} field",
+                pos.0, pos.1, extent.0, extent.1
+            ),
+            Error::InvalidExtent { extent, len } => write!(
+                f,
+                "{len} values can't fill a {}x{} field",
+                extent.0, extent.1
+            ),
+            Error::InvalidBand { lower, upper } => {
+                write!(f, "band from {lower} to {upper} is empty")
+            }
+            Error::InvalidInterval { interval } => {
+                write!(f, "contour interval {interval} isn't positive")
+            }
+            Error::InvalidLevelCount => write!(f, "level count must be positive"),
+            Error::InvalidImage(err) => write!(f, "invalid image: {err}"),
+            Error::InvalidTiff(err) => write!(f, "invalid TIFF: {err}"),
+            Error::Parse {
+                line: Some(line),
+                message,
+            } => write!(f, "line {line}: {message}"),
+            Error::Parse {
+                line: None,
+                message,
+            } => write!(f, "{message}"),
+            Error::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Unjoined { point } => write!(
+                f,
+                "several paths meet at ({}, {}), leaving them unjoined",
+                point.x, point.y
+            ),
+            Warning::DeadEnd { point } => write!(
+                f,
+                "contour at ({}, {}) has nowhere to go, ending it there",
+                point.x, point.y
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::InvalidImage(err) => Some(err),
+            Error::InvalidTiff(err) => Some(err),
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ImageError> for Error {
+    fn from(err: ImageError) -> Self {
+        Error::InvalidImage(err)
+    }
+}
+
+impl From<TiffError> for Error {
+    fn from(err: TiffError) -> Self {
+        Error::InvalidTiff(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
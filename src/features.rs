@@ -0,0 +1,184 @@
+//! Removal of contours too small to be worth drawing, such as the rings
+//! left around single noisy samples and short stubs cut off by the border.
+
+use crate::{marching_squares::ContourLevel, util::Path};
+
+/// Smallest features to keep, in pixels. Features under any of the limits
+/// are removed, and limits of zero keep everything.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFilter {
+    /// Area enclosed by a closed ring
+    pub min_area: f32,
+    /// Perimeter of a closed ring or length of an open line
+    pub min_length: f32,
+    /// Longer side of the box around a feature
+    pub min_size: f32,
+    /// Measure the interpolated geometry rather than the raw cell midpoints
+    pub interpolated: bool,
+}
+
+/// How many features of a level were removed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Removed {
+    pub elevation: f32,
+    /// Closed rings around ground higher than the level
+    pub pimples: usize,
+    /// Closed rings around ground lower than the level
+    pub pits: usize,
+    /// Open lines
+    pub stubs: usize,
+}
+
+impl Removed {
+    pub fn total(&self) -> usize {
+        self.pimples + self.pits + self.stubs
+    }
+}
+
+impl FeatureFilter {
+    /// Whether the path is at least as large as every limit.
+    pub fn keeps(&self, path: &Path) -> bool {
+        if path.points.len() < 2 {
+            return false;
+        }
+        let (min, max) = path.bounds(self.interpolated);
+        (max.x - min.x).max(max.y - min.y) >= self.min_size
+            && path.length(self.interpolated) >= self.min_length
+            && (!path.closed || path.signed_area(self.interpolated).abs() >= self.min_area)
+    }
+
+    /// Removes the features under the limits from every level, and returns
+    /// how many went from each.
+    pub fn apply(&self, levels: &mut [ContourLevel]) -> Vec<Removed> {
+        levels
+            .iter_mut()
+            .map(|level| {
+                let mut removed = Removed {
+                    elevation: level.elevation,
+                    ..Default::default()
+                };
+                level.paths.retain(|path| {
+                    if self.keeps(path) {
+                        return true;
+                    }
+                    // Rings keep higher ground on their right, so they go
+                    // clockwise on screen around a pimple.
+                    if !path.closed {
+                        removed.stubs += 1;
+                    } else if path.signed_area(self.interpolated) > 0.0 {
+                        removed.pimples += 1;
+                    } else {
+                        removed.pits += 1;
+                    }
+                    false
+                });
+                removed
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Field, Mode};
+
+    #[test]
+    fn filter_test() {
+        // A single low sample makes a tiny pit at 3 and a single high one a
+        // tiny ring at 5, where a high sample on the border makes a short
+        // open line.
+        let mut vals = vec![4.0; 100];
+        vals[3 * 10 + 3] = 9.0;
+        vals[6 * 10 + 6] = 0.0;
+        vals[6 * 10 + 9] = 9.0;
+        let big: Vec<f32> = (0..100)
+            .map(|i| if (i % 10) < 5 { 0.0 } else { 9.0 })
+            .collect();
+
+        let field = Field::new((10, 10), vals).unwrap();
+        let mut levels = field.contours(&[3.0, 5.0], false, Mode::Strict).unwrap().0;
+        let mut ridge = Field::new((10, 10), big)
+            .unwrap()
+            .contours(&[5.0], false, Mode::Strict)
+            .unwrap()
+            .0;
+        levels.append(&mut ridge);
+        assert_eq!(
+            vec![1, 2, 1],
+            levels.iter().map(|l| l.paths.len()).collect::<Vec<_>>()
+        );
+
+        let filter = FeatureFilter {
+            min_area: 2.0,
+            min_length: 5.0,
+            min_size: 0.0,
+            interpolated: true,
+        };
+        let removed = filter.apply(&mut levels);
+        assert_eq!(
+            vec![
+                Removed {
+                    elevation: 3.0,
+                    pimples: 0,
+                    pits: 1,
+                    stubs: 0
+                },
+                Removed {
+                    elevation: 5.0,
+                    pimples: 1,
+                    pits: 0,
+                    stubs: 1
+                },
+                Removed {
+                    elevation: 5.0,
+                    ..Default::default()
+                },
+            ],
+            removed
+        );
+        assert_eq!(
+            vec![0, 0, 1],
+            levels.iter().map(|l| l.paths.len()).collect::<Vec<_>>()
+        );
+
+        // Nothing goes without limits.
+        let mut levels = field.contours(&[3.0, 5.0], false, Mode::Strict).unwrap().0;
+        let removed = FeatureFilter::default().apply(&mut levels);
+        assert_eq!(0, removed.iter().map(Removed::total).sum::<usize>());
+
+        // Not even a line of two points, which cuts a single cell corner.
+        let mut levels = [ContourLevel {
+            elevation: 1.0,
+            paths: vec![Path::from_xy(&[(0.0, 0.5), (0.5, 0.0)], false)],
+            kind: None,
+        }];
+        assert_eq!(0, FeatureFilter::default().apply(&mut levels)[0].total());
+        assert_eq!(1, levels[0].paths.len());
+    }
+
+    #[test]
+    fn size_test() {
+        let field = Field::new(
+            (6, 3),
+            vec![
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, //
+                0.0, 1.0, 1.0, 1.0, 1.0, 0.0, //
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+            ],
+        )
+        .unwrap();
+        let mut levels = field.contours(&[0.5], false, Mode::Strict).unwrap().0;
+        let ring = &levels[0].paths[0];
+        let (min, max) = ring.bounds(true);
+        assert_eq!((0.5, 0.5, 4.5, 1.5), (min.x, min.y, max.x, max.y));
+
+        let filter = FeatureFilter {
+            min_size: 4.5,
+            interpolated: true,
+            ..Default::default()
+        };
+        assert!(!filter.keeps(ring));
+        assert_eq!(1, filter.apply(&mut levels)[0].pimples);
+    }
+}
@@ -0,0 +1,229 @@
+//! Smoothing filters run over the samples of a field before it's contoured.
+//!
+//! Every filter keeps the extent and georeferencing, and centres its window
+//! on the sample it replaces so contours stay where the source pixels are.
+//! Windows are cut off at the border of the field and samples without data
+//! are left out of them, with the weights of what remains scaled back up.
+//! Samples without data stay that way.
+
+use crate::marching_squares::Field;
+
+// Weights of a Gaussian kernel from its centre outwards, out to three sigma.
+fn gaussian_kernel(sigma: f32) -> Vec<f32> {
+    let radius = (3.0 * sigma).ceil() as usize;
+    (0..=radius)
+        .map(|d| (-((d * d) as f32) / (2.0 * sigma * sigma)).exp())
+        .collect()
+}
+
+impl Field {
+    // Applies `filter` to the window of valid samples around every valid
+    // sample, given as (dx, dy, value) with the centre value.
+    fn filter_windows(
+        mut self,
+        radius: usize,
+        mut filter: impl FnMut(f32, &[(i32, i32, f32)]) -> f32,
+    ) -> Self {
+        let (w, h) = (self.extent.0 as usize, self.extent.1 as usize);
+        let valid: Vec<bool> = self.vals.iter().map(|&v| self.is_valid(v)).collect();
+        let mut window = Vec::with_capacity((2 * radius + 1).pow(2));
+        let mut vals = self.vals.clone();
+        for y in 0..h {
+            for x in 0..w {
+                let centre = y * w + x;
+                if !valid[centre] {
+                    continue;
+                }
+                window.clear();
+                let (x0, x1) = (x.saturating_sub(radius), (x + radius).min(w - 1));
+                for wy in y.saturating_sub(radius)..=(y + radius).min(h - 1) {
+                    let row = wy * w;
+                    let samples = self.vals[row + x0..=row + x1]
+                        .iter()
+                        .zip(&valid[row + x0..=row + x1]);
+                    for (wx, (&val, &valid)) in (x0..).zip(samples) {
+                        if valid {
+                            window.push((wx as i32 - x as i32, wy as i32 - y as i32, val));
+                        }
+                    }
+                }
+                vals[centre] = filter(self.vals[centre], &window);
+            }
+        }
+        self.vals = vals;
+        self
+    }
+
+    /// Blurs the samples with a Gaussian of standard deviation `sigma`
+    /// pixels, in a row and then a column pass.
+    pub fn gaussian_blur(mut self, sigma: f32) -> Self {
+        if sigma <= 0.0 {
+            return self;
+        }
+        let kernel = gaussian_kernel(sigma);
+        let r = kernel.len() as i32 - 1;
+        let (w, h) = (self.extent.0 as i32, self.extent.1 as i32);
+        let index = |x: i32, y: i32| (x + y * w) as usize;
+
+        // Sums of weighted values and of weights along the rows, so the
+        // column pass can scale by the weight of the valid samples in the
+        // whole square.
+        let mut sums = vec![(0.0f32, 0.0f32); self.vals.len()];
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = (0.0, 0.0);
+                for wx in (x - r).max(0)..=(x + r).min(w - 1) {
+                    let val = self.vals[index(wx, y)];
+                    if self.is_valid(val) {
+                        let weight = kernel[(wx - x).unsigned_abs() as usize];
+                        sum = (sum.0 + weight * val, sum.1 + weight);
+                    }
+                }
+                sums[index(x, y)] = sum;
+            }
+        }
+        for y in 0..h {
+            for x in 0..w {
+                if !self.is_valid(self.vals[index(x, y)]) {
+                    continue;
+                }
+                let mut sum = (0.0, 0.0);
+                for wy in (y - r).max(0)..=(y + r).min(h - 1) {
+                    let weight = kernel[(wy - y).unsigned_abs() as usize];
+                    let (value, total) = sums[index(x, wy)];
+                    sum = (sum.0 + weight * value, sum.1 + weight * total);
+                }
+                self.vals[index(x, y)] = sum.0 / sum.1;
+            }
+        }
+        self
+    }
+
+    /// Replaces every sample by the median of the square of samples reaching
+    /// `radius` pixels around it, which removes spikes without blurring
+    /// ridges.
+    pub fn median_filter(self, radius: usize) -> Self {
+        if radius == 0 {
+            return self;
+        }
+        let mut vals = Vec::new();
+        self.filter_windows(radius, |_, window| {
+            vals.clear();
+            vals.extend(window.iter().map(|&(_, _, val)| val));
+            let middle = vals.len() / 2;
+            *vals.select_nth_unstable_by(middle, f32::total_cmp).1
+        })
+    }
+
+    /// Averages every sample with those around it weighted both by distance,
+    /// with a standard deviation of `sigma_space` pixels, and by difference
+    /// in value, with a standard deviation of `sigma_range`. Noise is smoothed
+    /// while breaks of slope larger than `sigma_range` stay sharp.
+    pub fn bilateral_filter(self, sigma_space: f32, sigma_range: f32) -> Self {
+        if sigma_space <= 0.0 || sigma_range <= 0.0 {
+            return self;
+        }
+        let kernel = gaussian_kernel(sigma_space);
+        let range = 2.0 * sigma_range * sigma_range;
+        self.filter_windows(kernel.len() - 1, |centre, window| {
+            let (sum, total) = window
+                .iter()
+                .fold((0.0, 0.0), |(sum, total), &(dx, dy, val)| {
+                    let difference = val - centre;
+                    let weight = kernel[dx.unsigned_abs() as usize]
+                        * kernel[dy.unsigned_abs() as usize]
+                        * (-difference * difference / range).exp();
+                    (sum + weight * val, total + weight)
+                });
+            sum / total
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaussian_test() {
+        let mut vals = vec![0.0; 49];
+        vals[24] = 1.0;
+        let field = Field::new((7, 7), vals).unwrap().gaussian_blur(1.0);
+
+        // The impulse spreads out evenly around where it was.
+        assert!(field.vals[24] > field.vals[23] && field.vals[23] > field.vals[22]);
+        for (a, b) in [(23, 25), (17, 31), (16, 32), (10, 38)] {
+            assert!((field.vals[a] - field.vals[b]).abs() < 1e-6);
+        }
+
+        // A flat field stays flat right up to its border.
+        let field = Field::new((5, 4), vec![3.0; 20])
+            .unwrap()
+            .gaussian_blur(2.0);
+        assert!(field.vals.iter().all(|&v| (v - 3.0).abs() < 1e-5));
+
+        // A ramp keeps its middle where it was.
+        let vals = (0..50).map(|i| (i % 10) as f32).collect();
+        let field = Field::new((10, 5), vals).unwrap().gaussian_blur(1.0);
+        assert!((field.vals[24] + field.vals[25] - 9.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn median_test() {
+        let mut vals = vec![5.0; 25];
+        vals[12] = 100.0;
+        vals[0] = 0.0;
+        let field = Field::new((5, 5), vals).unwrap().median_filter(1);
+        assert!(field.vals.iter().all(|&v| v == 5.0));
+    }
+
+    #[test]
+    fn bilateral_test() {
+        // A step much higher than the range sigma stays sharp, small noise
+        // is evened out.
+        let vals = (0..64)
+            .map(|i| {
+                let noise = if i % 3 == 0 { 0.1 } else { -0.05 };
+                if i % 8 < 4 {
+                    noise
+                } else {
+                    10.0 + noise
+                }
+            })
+            .collect();
+        let field = Field::new((8, 8), vals).unwrap().bilateral_filter(1.5, 0.5);
+        for (i, &v) in field.vals.iter().enumerate() {
+            let expected = if i % 8 < 4 { 0.0 } else { 10.0 };
+            assert!((v - expected).abs() < 0.06, "{i}: {v}");
+        }
+    }
+
+    #[test]
+    fn nodata_test() {
+        let mut vals = vec![1.0; 25];
+        vals[7] = -9999.0;
+        vals[13] = f32::NAN;
+        for field in [
+            Field::new((5, 5), vals.clone())
+                .unwrap()
+                .with_nodata(-9999.0)
+                .gaussian_blur(1.0),
+            Field::new((5, 5), vals.clone())
+                .unwrap()
+                .with_nodata(-9999.0)
+                .median_filter(2),
+            Field::new((5, 5), vals.clone())
+                .unwrap()
+                .with_nodata(-9999.0)
+                .bilateral_filter(1.0, 1.0),
+        ] {
+            assert_eq!(-9999.0, field.vals[7]);
+            assert!(field.vals[13].is_nan());
+            for (i, &v) in field.vals.iter().enumerate() {
+                if i != 7 && i != 13 {
+                    assert!((v - 1.0).abs() < 1e-5);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+//! Georeferencing, mapping sample positions in a [`Field`] to coordinates in
+//! its coordinate reference system, and reading it from GeoTIFF tags.
+
+use std::{fs::File, io::BufReader, path::Path as FsPath};
+
+use image::ImageError;
+use tiff::{
+    decoder::{Decoder, DecodingResult},
+    tags::Tag,
+};
+
+use crate::{
+    error::Result,
+    marching_squares::Field,
+    util::{Path, Point, RichPoint},
+};
+
+// GeoKeys used to find the raster's pixel convention and CRS.
+const RASTER_TYPE_KEY: u16 = 1025;
+const GEOGRAPHIC_TYPE_KEY: u16 = 2048;
+const PROJECTED_CS_TYPE_KEY: u16 = 3072;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+/// Affine map from sample positions to map coordinates, with the
+/// coefficients in the same order as GDAL's geotransform:
+///
+/// ```text
+/// x = c[0] + c[1] * column + c[2] * row
+/// y = c[3] + c[4] * column + c[5] * row
+/// ```
+///
+/// Sample positions are those of the field, so `(0, 0)` is the centre of the
+/// first pixel rather than its corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoTransform(pub [f64; 6]);
+
+impl GeoTransform {
+    /// Leaves sample positions as they are.
+    pub const IDENTITY: GeoTransform = GeoTransform([0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+
+    /// A north-up transform from the map position of the first sample and the
+    /// size of a pixel. Rows run southwards, so `pixel_size.1` is positive.
+    pub fn north_up(origin: (f64, f64), pixel_size: (f64, f64)) -> Self {
+        GeoTransform([origin.0, pixel_size.0, 0.0, origin.1, 0.0, -pixel_size.1])
+    }
+
+    /// Web Mercator (EPSG:3857) position of a web map tile `x`, `y` at zoom
+    /// `z`, with y counting tiles down from the north, for a raster of the
+    /// tile with the given extent.
+    pub fn xyz_tile(z: u32, x: u32, y: u32, extent: (u32, u32)) -> Self {
+        // Circumference of the sphere Web Mercator projects.
+        let world = 2.0 * std::f64::consts::PI * 6_378_137.0;
+        let tile = world / 2f64.powi(z as i32);
+        let pixel = (tile / extent.0 as f64, tile / extent.1 as f64);
+        let left = -world / 2.0 + x as f64 * tile;
+        let top = world / 2.0 - y as f64 * tile;
+        GeoTransform::north_up((left + pixel.0 / 2.0, top - pixel.1 / 2.0), pixel)
+    }
+
+    /// Maps a position in the field to map coordinates.
+    pub fn apply(&self, point: Point) -> (f64, f64) {
+        let [x0, xc, xr, y0, yc, yr] = self.0;
+        let (column, row) = (point.x as f64, point.y as f64);
+        (x0 + xc * column + xr * row, y0 + yc * column + yr * row)
+    }
+
+    /// Whether the transform flips the field over, as north-up transforms do
+    /// with rows running down and y running up. Rings change winding when
+    /// they're mapped through a flipping transform.
+    pub fn flips(&self) -> bool {
+        let [_, xc, xr, _, yc, yr] = self.0;
+        xc * yr - xr * yc < 0.0
+    }
+
+    /// Side of a square with the area of one pixel, in map units.
+    pub fn pixel_size(&self) -> f64 {
+        let [_, xc, xr, _, yc, yr] = self.0;
+        (xc * yr - xr * yc).abs().sqrt()
+    }
+
+    // Builds the transform from the GeoTIFF model tags, where raster
+    // coordinates put the corner of the first pixel at (0, 0) unless the
+    // raster is marked as PixelIsPoint.
+    fn from_tags(
+        tiepoint: Option<&[f64]>,
+        scale: Option<&[f64]>,
+        matrix: Option<&[f64]>,
+        pixel_is_point: bool,
+    ) -> Option<Self> {
+        let offset = if pixel_is_point { 0.0 } else { 0.5 };
+        let raster = if let Some(m) = matrix.filter(|m| m.len() >= 8) {
+            [m[3], m[0], m[1], m[7], m[4], m[5]]
+        } else {
+            let (tiepoint, scale) = (tiepoint?, scale?);
+            if tiepoint.len() < 6 || scale.len() < 2 {
+                return None;
+            }
+            let (i, j, x, y) = (tiepoint[0], tiepoint[1], tiepoint[3], tiepoint[4]);
+            [
+                x - i * scale[0],
+                scale[0],
+                0.0,
+                y + j * scale[1],
+                0.0,
+                -scale[1],
+            ]
+        };
+        let [x0, xc, xr, y0, yc, yr] = raster;
+        Some(GeoTransform([
+            x0 + (xc + xr) * offset,
+            xc,
+            xr,
+            y0 + (yc + yr) * offset,
+            yc,
+            yr,
+        ]))
+    }
+}
+
+/// Which of the traced geometry a writer uses, and the coordinates it's
+/// written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    /// Use the interpolated geometry rather than the raw cell midpoints
+    pub interpolated: bool,
+    /// Write coordinates in map units through this transform instead of pixels
+    pub transform: Option<GeoTransform>,
+}
+
+impl Default for Geometry {
+    fn default() -> Self {
+        Self {
+            interpolated: true,
+            transform: None,
+        }
+    }
+}
+
+impl Geometry {
+    // One pixel, in whichever units the coordinates are written in.
+    pub(crate) fn pixel_size(&self) -> f64 {
+        self.transform
+            .map_or(1.0, |transform| transform.pixel_size())
+    }
+
+    pub(crate) fn point(&self, point: &RichPoint) -> (f64, f64) {
+        self.transform
+            .unwrap_or(GeoTransform::IDENTITY)
+            .apply(point.point(self.interpolated))
+    }
+
+    pub(crate) fn coordinates(&self, path: &Path) -> Vec<(f64, f64)> {
+        path.points.iter().map(|p| self.point(p)).collect()
+    }
+
+    // The coordinates of a path as a closed ring, wound clockwise or
+    // counter-clockwise in the coordinates it's written in with y up.
+    pub(crate) fn ring(&self, path: &Path, clockwise: bool) -> Vec<(f64, f64)> {
+        let mut coordinates = self.coordinates(path);
+        if coordinates.first() != coordinates.last() {
+            coordinates.push(coordinates[0]);
+        }
+        let twice_area: f64 = coordinates
+            .windows(2)
+            .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+            .sum();
+        if (twice_area < 0.0) != clockwise {
+            coordinates.reverse();
+        }
+        coordinates
+    }
+}
+
+const WGS84_GEOGCS: &str = "GEOGCS[\"GCS_WGS_1984\",DATUM[\"D_WGS_1984\",SPHEROID[\"WGS_1984\",6378137.0,298.257223563]],PRIMEM[\"Greenwich\",0.0],UNIT[\"Degree\",0.0174532925199433]]";
+const NAD83_GEOGCS: &str = "GEOGCS[\"GCS_North_American_1983\",DATUM[\"D_North_American_1983\",SPHEROID[\"GRS_1980\",6378137.0,298.257222101]],PRIMEM[\"Greenwich\",0.0],UNIT[\"Degree\",0.0174532925199433]]";
+
+/// ESRI flavoured WKT for the coordinate reference systems DEMs most often
+/// come in: WGS 84 and NAD83 and their UTM zones. Other EPSG codes give
+/// `None`.
+pub fn esri_wkt(epsg: u32) -> Option<String> {
+    let utm = |name: &str, geogcs: &str, zone: u32, south: bool| {
+        format!(
+            "PROJCS[\"{name}_UTM_Zone_{zone}{}\",{geogcs},PROJECTION[\"Transverse_Mercator\"],\
+             PARAMETER[\"False_Easting\",500000.0],PARAMETER[\"False_Northing\",{:.1}],\
+             PARAMETER[\"Central_Meridian\",{:.1}],PARAMETER[\"Scale_Factor\",0.9996],\
+             PARAMETER[\"Latitude_Of_Origin\",0.0],UNIT[\"Meter\",1.0]]",
+            if south { "S" } else { "N" },
+            if south { 10_000_000.0 } else { 0.0 },
+            zone as f64 * 6.0 - 183.0,
+        )
+    };
+    match epsg {
+        4326 => Some(WGS84_GEOGCS.to_owned()),
+        4269 => Some(NAD83_GEOGCS.to_owned()),
+        32601..=32660 => Some(utm("WGS_1984", WGS84_GEOGCS, epsg - 32600, false)),
+        32701..=32760 => Some(utm("WGS_1984", WGS84_GEOGCS, epsg - 32700, true)),
+        26901..=26923 => Some(utm("NAD_1983", NAD83_GEOGCS, epsg - 26900, false)),
+        _ => None,
+    }
+}
+
+// Looks up a short GeoKey value stored directly in the key directory.
+fn geo_key(directory: &[u16], key: u16) -> Option<u16> {
+    directory
+        .get(4..)?
+        .chunks_exact(4)
+        .find(|entry| entry[0] == key && entry[1] == 0)
+        .map(|entry| entry[3])
+}
+
+impl Field {
+    /// Maps the field onto a coordinate reference system.
+    pub fn with_transform(mut self, transform: GeoTransform) -> Self {
+        self.transform = Some(transform);
+        self
+    }
+
+    /// Records the EPSG code of the field's coordinate reference system.
+    pub fn with_crs(mut self, epsg: u32) -> Self {
+        self.crs = Some(epsg);
+        self
+    }
+
+    /// Map coordinates of a position in the field, which are the position
+    /// itself when the field isn't georeferenced.
+    pub fn to_map(&self, point: Point) -> (f64, f64) {
+        self.transform
+            .unwrap_or(GeoTransform::IDENTITY)
+            .apply(point)
+    }
+
+    /// Loads the first band of a TIFF, along with its georeferencing, CRS and
+    /// nodata value when it has GeoTIFF tags for them.
+    pub fn open_geotiff(path: impl AsRef<FsPath>) -> Result<Self> {
+        let mut decoder = Decoder::new(BufReader::new(
+            File::open(path).map_err(ImageError::IoError)?,
+        ))?;
+        let (width, height) = decoder.dimensions()?;
+
+        let samples: Vec<f32> = match decoder.read_image()? {
+            DecodingResult::U8(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::U16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::U32(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::U64(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::F32(v) => v,
+            DecodingResult::F64(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::I8(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::I16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::I32(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::I64(v) => v.into_iter().map(|x| x as f32).collect(),
+        };
+        let bands = (samples.len() / (width as usize * height as usize)).max(1);
+        let vals = samples.into_iter().step_by(bands).collect();
+        let mut field = Field::new((width, height), vals)?;
+
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).ok();
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).ok();
+        let matrix = decoder.get_tag_f64_vec(Tag::ModelTransformationTag).ok();
+        let keys = decoder
+            .get_tag_u16_vec(Tag::GeoKeyDirectoryTag)
+            .unwrap_or_default();
+        let pixel_is_point = geo_key(&keys, RASTER_TYPE_KEY) == Some(RASTER_PIXEL_IS_POINT);
+
+        field.transform = GeoTransform::from_tags(
+            tiepoint.as_deref(),
+            scale.as_deref(),
+            matrix.as_deref(),
+            pixel_is_point,
+        );
+        field.crs = geo_key(&keys, PROJECTED_CS_TYPE_KEY)
+            .or_else(|| geo_key(&keys, GEOGRAPHIC_TYPE_KEY))
+            .filter(|&code| code != 0 && code != u16::MAX)
+            .map(u32::from);
+        field.nodata = decoder
+            .get_tag_ascii_string(Tag::GdalNodata)
+            .ok()
+            .and_then(|nodata| nodata.trim().parse().ok());
+        Ok(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiepoint_test() {
+        let tiepoint = [0.0, 0.0, 0.0, 1000.0, 5000.0, 0.0];
+        let scale = [10.0, 20.0, 0.0];
+
+        // The first sample is at the centre of the first pixel.
+        let area = GeoTransform::from_tags(Some(&tiepoint), Some(&scale), None, false).unwrap();
+        assert_eq!((1005.0, 4990.0), area.apply(Point { x: 0.0, y: 0.0 }));
+        assert_eq!((1025.0, 4950.0), area.apply(Point { x: 2.0, y: 2.0 }));
+        assert!(area.flips());
+
+        let point = GeoTransform::from_tags(Some(&tiepoint), Some(&scale), None, true).unwrap();
+        assert_eq!((1000.0, 5000.0), point.apply(Point { x: 0.0, y: 0.0 }));
+        assert_eq!(
+            GeoTransform::north_up((1000.0, 5000.0), (10.0, 20.0)),
+            point
+        );
+
+        assert!(GeoTransform::from_tags(Some(&tiepoint), None, None, false).is_none());
+    }
+
+    #[test]
+    fn matrix_test() {
+        #[rustfmt::skip]
+        let matrix = [
+            2.0, 1.0, 0.0, 100.0,
+            0.0, -2.0, 0.0, 200.0,
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 0.0, 0.0, 1.0,
+        ];
+        let transform = GeoTransform::from_tags(None, None, Some(&matrix), true).unwrap();
+        assert_eq!(GeoTransform([100.0, 2.0, 1.0, 200.0, 0.0, -2.0]), transform);
+        assert_eq!((104.0, 198.0), transform.apply(Point { x: 1.5, y: 1.0 }));
+    }
+
+    #[test]
+    fn xyz_tile_test() {
+        let half = 20_037_508.342789244;
+        let world = GeoTransform::xyz_tile(0, 0, 0, (256, 256));
+        let (x, y) = world.apply(Point { x: -0.5, y: -0.5 });
+        assert!((x + half).abs() < 1e-6 && (y - half).abs() < 1e-6);
+        let (x, y) = world.apply(Point { x: 255.5, y: 255.5 });
+        assert!((x - half).abs() < 1e-6 && (y + half).abs() < 1e-6);
+
+        // The south east quarter of the world at zoom 1.
+        let tile = GeoTransform::xyz_tile(1, 1, 1, (512, 512));
+        let (x, y) = tile.apply(Point { x: -0.5, y: -0.5 });
+        assert!(x.abs() < 1e-6 && y.abs() < 1e-6);
+        assert!((tile.pixel_size() - half / 512.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn geo_key_test() {
+        let keys = [1, 1, 0, 3, 1024, 0, 1, 1, 1025, 0, 1, 2, 3072, 0, 1, 26910];
+        assert_eq!(Some(2), geo_key(&keys, RASTER_TYPE_KEY));
+        assert_eq!(Some(26910), geo_key(&keys, PROJECTED_CS_TYPE_KEY));
+        assert_eq!(None, geo_key(&keys, GEOGRAPHIC_TYPE_KEY));
+        assert_eq!(None, geo_key(&[], RASTER_TYPE_KEY));
+    }
+
+    #[test]
+    fn open_geotiff_test() {
+        let field = Field::open("maple_bay_square.tif").unwrap();
+        assert_eq!((543, 543), field.extent);
+        assert_eq!(Some(26910), field.crs);
+
+        let (x, y) = field.to_map(Point { x: 0.0, y: 0.0 });
+        let half_pixel = 15.654489628499281 / 2.0;
+        assert!((x - (451823.9760731193 + half_pixel)).abs() < 1e-6);
+        assert!((y - (5411225.757845975 - half_pixel)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn esri_wkt_test() {
+        assert_eq!(
+            "PROJCS[\"NAD_1983_UTM_Zone_10N\",GEOGCS[\"GCS_North_American_1983\",\
+             DATUM[\"D_North_American_1983\",SPHEROID[\"GRS_1980\",6378137.0,298.257222101]],\
+             PRIMEM[\"Greenwich\",0.0],UNIT[\"Degree\",0.0174532925199433]],\
+             PROJECTION[\"Transverse_Mercator\"],PARAMETER[\"False_Easting\",500000.0],\
+             PARAMETER[\"False_Northing\",0.0],PARAMETER[\"Central_Meridian\",-123.0],\
+             PARAMETER[\"Scale_Factor\",0.9996],PARAMETER[\"Latitude_Of_Origin\",0.0],\
+             UNIT[\"Meter\",1.0]]",
+            esri_wkt(26910).unwrap()
+        );
+        assert!(esri_wkt(32733)
+            .unwrap()
+            .contains("Zone_33S\",GEOGCS[\"GCS_WGS_1984\""));
+        assert!(esri_wkt(32733)
+            .unwrap()
+            .contains("False_Northing\",10000000.0],PARAMETER[\"Central_Meridian\",15.0]"));
+        assert_eq!(None, esri_wkt(3857));
+    }
+}
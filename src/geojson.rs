@@ -0,0 +1,196 @@
+//! GeoJSON output, with a feature for every contour or band polygon.
+
+use std::io::{self, Write};
+
+use itertools::join;
+
+use crate::{
+    band::ContourBand,
+    geo::Geometry,
+    marching_squares::{ContourKind, ContourLevel},
+    util::Path,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct GeoJsonOptions {
+    pub geometry: Geometry,
+}
+
+impl GeoJsonOptions {
+    fn coordinates(&self, path: &Path) -> Vec<(f64, f64)> {
+        self.geometry.coordinates(path)
+    }
+
+    // A polygon ring wound counter-clockwise, or clockwise for a hole, as
+    // RFC 7946 asks.
+    fn ring(&self, path: &Path, hole: bool) -> String {
+        position_list(&self.geometry.ring(path, hole))
+    }
+}
+
+fn position_list(coordinates: &[(f64, f64)]) -> String {
+    format!(
+        "[{}]",
+        join(coordinates.iter().map(|(x, y)| format!("[{x},{y}]")), ",")
+    )
+}
+
+// JSON has no infinities, so unbounded band limits are written as null.
+fn number(val: f32) -> String {
+    if val.is_finite() {
+        val.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+/// Writes a FeatureCollection with a LineString feature for every path, with
+/// the elevation of its level, whether it's closed and the kind of contour
+/// when the levels are classified.
+pub fn write_geojson<W: Write>(
+    out: &mut W,
+    levels: &[ContourLevel],
+    options: &GeoJsonOptions,
+) -> io::Result<()> {
+    writeln!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+
+    let paths = levels.iter().flat_map(|level| {
+        level
+            .paths
+            .iter()
+            .filter(|path| path.points.len() > 1)
+            .map(move |path| (level, path))
+    });
+    for (i, (level, path)) in paths.enumerate() {
+        let kind = match level.kind {
+            Some(ContourKind::Index) => ",\"kind\":\"index\"",
+            Some(ContourKind::Intermediate) => ",\"kind\":\"intermediate\"",
+            None => "",
+        };
+        writeln!(
+            out,
+            "{}{{\"type\":\"Feature\",\"properties\":{{\"elevation\":{},\"closed\":{}{kind}}},\"geometry\":{{\"type\":\"LineString\",\"coordinates\":{}}}}}",
+            if i > 0 { "," } else { "" },
+            number(level.elevation),
+            path.closed,
+            position_list(&options.coordinates(path)),
+        )?;
+    }
+
+    writeln!(out, "]}}")
+}
+
+/// Writes a FeatureCollection with a Polygon feature for every polygon of
+/// every band, with the band's lower and upper limits.
+pub fn write_bands_geojson<W: Write>(
+    out: &mut W,
+    bands: &[ContourBand],
+    options: &GeoJsonOptions,
+) -> io::Result<()> {
+    writeln!(out, "{{\"type\":\"FeatureCollection\",\"features\":[")?;
+
+    let polygons = bands
+        .iter()
+        .flat_map(|band| band.polygons.iter().map(move |polygon| (band, polygon)));
+    for (i, (band, polygon)) in polygons.enumerate() {
+        let rings = std::iter::once(options.ring(&polygon.exterior, false))
+            .chain(polygon.holes.iter().map(|hole| options.ring(hole, true)));
+        writeln!(
+            out,
+            "{}{{\"type\":\"Feature\",\"properties\":{{\"lower\":{},\"upper\":{}}},\"geometry\":{{\"type\":\"Polygon\",\"coordinates\":[{}]}}}}",
+            if i > 0 { "," } else { "" },
+            number(band.lower),
+            number(band.upper),
+            join(rings, ","),
+        )?;
+    }
+
+    writeln!(out, "]}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geo::GeoTransform, polygon::Polygon, util::Path};
+
+    #[test]
+    fn geojson_test() {
+        let ring = Path::from_xy(&[(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 0.0)], true);
+        let levels = [ContourLevel {
+            elevation: 100.0,
+            paths: vec![Path::from_xy(&[(0.5, 0.0), (1.0, 0.5)], false), ring],
+            kind: Some(ContourKind::Index),
+        }];
+        let options = GeoJsonOptions {
+            geometry: Geometry {
+                interpolated: true,
+                transform: Some(GeoTransform::north_up((1000.0, 2000.0), (10.0, 10.0))),
+            },
+        };
+
+        let mut out = Vec::new();
+        write_geojson(&mut out, &levels, &options).unwrap();
+        assert_eq!(
+            "{\"type\":\"FeatureCollection\",\"features\":[\n\
+             {\"type\":\"Feature\",\"properties\":{\"elevation\":100,\"closed\":false,\"kind\":\"index\"},\
+             \"geometry\":{\"type\":\"LineString\",\"coordinates\":[[1005,2000],[1010,1995]]}}\n\
+             ,{\"type\":\"Feature\",\"properties\":{\"elevation\":100,\"closed\":true,\"kind\":\"index\"},\
+             \"geometry\":{\"type\":\"LineString\",\"coordinates\":[[1000,2000],[1020,2000],[1020,1980],[1000,2000]]}}\n\
+             ]}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn bands_test() {
+        // Clockwise on screen is clockwise on a north-up map too, so the
+        // exterior is reversed.
+        let mut hole = Path::from_xy(&[(1.0, 1.0), (2.0, 1.0), (2.0, 2.0), (1.0, 1.0)], true);
+        hole.reverse();
+        let bands = [ContourBand {
+            lower: 5.0,
+            upper: f32::INFINITY,
+            polygons: vec![Polygon {
+                exterior: Path::from_xy(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 0.0)], true),
+                holes: vec![hole],
+            }],
+        }];
+        let options = GeoJsonOptions {
+            geometry: Geometry {
+                interpolated: true,
+                transform: Some(GeoTransform::north_up((0.0, 0.0), (1.0, 1.0))),
+            },
+        };
+
+        let mut out = Vec::new();
+        write_bands_geojson(&mut out, &bands, &options).unwrap();
+        assert_eq!(
+            "{\"type\":\"FeatureCollection\",\"features\":[\n\
+             {\"type\":\"Feature\",\"properties\":{\"lower\":5,\"upper\":null},\
+             \"geometry\":{\"type\":\"Polygon\",\"coordinates\":[\
+             [[0,0],[4,-4],[4,0],[0,0]],[[1,-1],[2,-1],[2,-2],[1,-1]]]}}\n\
+             ]}\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn winding_test() {
+        let options = GeoJsonOptions::default();
+        let square = Path::from_xy(
+            &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)],
+            true,
+        );
+
+        // In pixel coordinates y points down, so the ring is counter-clockwise
+        // as it stands and gets reversed as a hole.
+        assert_eq!(
+            "[[0,0],[1,0],[1,1],[0,1],[0,0]]",
+            options.ring(&square, false)
+        );
+        assert_eq!(
+            "[[0,0],[0,1],[1,1],[1,0],[0,0]]",
+            options.ring(&square, true)
+        );
+    }
+}
@@ -0,0 +1,436 @@
+//! Placement of elevation labels along contours, on their straightest
+//! stretches and clear of each other.
+
+use crate::{
+    marching_squares::{ContourKind, ContourLevel},
+    smooth::on_border,
+    util::{Path, Point},
+};
+
+#[derive(Debug, Clone)]
+pub struct LabelOptions {
+    /// Height of the text in pixels
+    pub font_size: f32,
+    /// Shortest distance along a contour between two of its labels, in pixels
+    pub spacing: f32,
+    /// Most the contour may turn under a label, in radians
+    pub max_turn: f32,
+    /// Furthest the contour may stray from a straight line under a label, as
+    /// a fraction of the font size
+    pub max_deviation: f32,
+    /// Decimal places of the elevations, or the fewest that tell the levels
+    /// apart if unset
+    pub decimals: Option<usize>,
+}
+
+impl Default for LabelOptions {
+    fn default() -> Self {
+        Self {
+            font_size: 12.0,
+            spacing: 400.0,
+            max_turn: 0.5,
+            max_deviation: 0.25,
+            decimals: None,
+        }
+    }
+}
+
+impl LabelOptions {
+    // Room along the path a label takes, with some space either side. Digits
+    // are about 0.6 of the font size wide.
+    fn length(&self, text: &str) -> f32 {
+        (text.chars().count() as f32 * 0.6 + 1.0) * self.font_size
+    }
+}
+
+// The fewest decimal places, up to 6, that round no level by more than a
+// tenth of the smallest gap between levels, so they all read differently.
+// A lone level is written as it is.
+fn decimals(levels: &[ContourLevel]) -> usize {
+    let mut elevations: Vec<f64> = levels.iter().map(|level| level.elevation as f64).collect();
+    elevations.sort_by(f64::total_cmp);
+    elevations.dedup();
+    let tolerance = elevations
+        .windows(2)
+        .map(|w| (w[1] - w[0]) / 10.0)
+        .reduce(f64::min)
+        .unwrap_or(0.0);
+    (0..6)
+        .find(|&decimals| {
+            elevations.iter().all(|&elevation| {
+                let rounded: f64 = format!("{elevation:.decimals$}").parse().unwrap();
+                (rounded - elevation).abs() <= tolerance.max(elevation.abs() * 1e-6)
+            })
+        })
+        .unwrap_or(6)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub text: String,
+    /// Index of the level the label belongs to
+    pub level: usize,
+    /// Index of the path in its level
+    pub path: usize,
+    /// Stretch of the path under the label, running so the text reads left
+    /// to right with y down
+    pub baseline: Vec<Point>,
+    /// Middle of the label on the path
+    pub centre: Point,
+    /// Direction of the text in radians, clockwise from the x axis with y down
+    pub angle: f32,
+}
+
+impl Label {
+    /// Top left and bottom right of a box around the label, padded by 0.6 of
+    /// the font size for the height of the text.
+    fn bounds(&self, options: &LabelOptions) -> (Point, Point) {
+        let pad = options.font_size * 0.6;
+        let (mut min, mut max) = (self.baseline[0], self.baseline[0]);
+        for p in &self.baseline {
+            min = Point {
+                x: min.x.min(p.x),
+                y: min.y.min(p.y),
+            };
+            max = Point {
+                x: max.x.max(p.x),
+                y: max.y.max(p.y),
+            };
+        }
+        (
+            Point {
+                x: min.x - pad,
+                y: min.y - pad,
+            },
+            Point {
+                x: max.x + pad,
+                y: max.y + pad,
+            },
+        )
+    }
+}
+
+fn overlaps(a: (Point, Point), b: (Point, Point)) -> bool {
+    a.0.x < b.1.x && b.0.x < a.1.x && a.0.y < b.1.y && b.0.y < a.1.y
+}
+
+// Distance along the path at every point.
+fn distances(points: &[Point]) -> Vec<f32> {
+    let mut total = 0.0;
+    let mut distances = Vec::with_capacity(points.len());
+    distances.push(0.0);
+    for w in points.windows(2) {
+        total += (w[1].x - w[0].x).hypot(w[1].y - w[0].y);
+        distances.push(total);
+    }
+    distances
+}
+
+// The part of the path between two distances along it.
+fn stretch(points: &[Point], distances: &[f32], from: f32, to: f32) -> Vec<Point> {
+    let at = |d: f32| {
+        let i = distances
+            .partition_point(|&x| x <= d)
+            .clamp(1, points.len() - 1);
+        let (a, b) = (points[i - 1], points[i]);
+        let span = distances[i] - distances[i - 1];
+        let t = if span > 0.0 {
+            (d - distances[i - 1]) / span
+        } else {
+            0.0
+        };
+        Point {
+            x: a.x + (b.x - a.x) * t,
+            y: a.y + (b.y - a.y) * t,
+        }
+    };
+    let mut stretch = vec![at(from)];
+    stretch.extend(
+        points
+            .iter()
+            .zip(distances)
+            .filter(|&(_, &d)| d > from && d < to)
+            .map(|(&p, _)| p),
+    );
+    stretch.push(at(to));
+    stretch
+}
+
+// How far the stretch bends, as the angle between the chords of its two
+// halves, and how far it strays from its chord. Chords rather than segments
+// so the staircase of contours along pixel rows doesn't count as bending.
+fn bend(stretch: &[Point], middle: Point) -> (f32, f32) {
+    let (first, last) = (stretch[0], stretch[stretch.len() - 1]);
+    let angle = |a: Point, b: Point| (b.y - a.y).atan2(b.x - a.x);
+    let turn = (angle(middle, last) - angle(first, middle)).rem_euclid(std::f32::consts::TAU);
+    let (dx, dy) = (last.x - first.x, last.y - first.y);
+    let chord = dx.hypot(dy);
+    let deviation = stretch
+        .iter()
+        .map(|p| ((p.x - first.x) * dy - (p.y - first.y) * dx).abs() / chord)
+        .fold(0.0, f32::max);
+    (turn.min(std::f32::consts::TAU - turn), deviation)
+}
+
+struct Candidate {
+    position: f32,
+    /// Length of the path when it's a closed ring, which positions wrap around
+    ring: Option<f32>,
+    score: f32,
+    label: Label,
+}
+
+fn candidates(
+    level: usize,
+    index: usize,
+    path: &Path,
+    text: &str,
+    extent: (u32, u32),
+    options: &LabelOptions,
+    interpolated: bool,
+) -> Vec<Candidate> {
+    let points: Vec<Point> = path.points.iter().map(|p| p.point(interpolated)).collect();
+    if points.len() < 2 {
+        return Vec::new();
+    }
+    let distances = distances(&points);
+    let total = distances[distances.len() - 1];
+    let half = options.length(text) / 2.0;
+
+    let mut candidates = Vec::new();
+    let mut position = half;
+    while position + half <= total {
+        let mut baseline = stretch(&points, &distances, position - half, position + half);
+        let centre = stretch(&points, &distances, position, position)[0];
+        let (turn, deviation) = bend(&baseline, centre);
+        let deviation = deviation / options.font_size;
+        // Edges closed along the border of the field aren't contours.
+        let border = baseline.iter().any(|&p| on_border(p, extent));
+        if turn <= options.max_turn && deviation <= options.max_deviation && !border {
+            let (first, last) = (baseline[0], baseline[baseline.len() - 1]);
+            if last.x < first.x {
+                baseline.reverse();
+            }
+            let (first, last) = (baseline[0], baseline[baseline.len() - 1]);
+            candidates.push(Candidate {
+                position,
+                ring: path.closed.then_some(total),
+                score: turn / options.max_turn + deviation / options.max_deviation,
+                label: Label {
+                    text: text.to_owned(),
+                    level,
+                    path: index,
+                    baseline,
+                    centre,
+                    angle: (last.y - first.y).atan2(last.x - first.x),
+                },
+            });
+        }
+        position += options.font_size;
+    }
+    candidates
+}
+
+/// Places labels with the elevation of their level along the contours of a
+/// field of the given extent, leaving out intermediate contours when the
+/// levels are classified and stretches along the border.
+/// The straightest stretches are taken first, and labels that would
+/// overlap one already placed, or come closer to another on the same
+/// contour than the spacing, are left out.
+pub fn place_labels(
+    levels: &[ContourLevel],
+    extent: (u32, u32),
+    options: &LabelOptions,
+    interpolated: bool,
+) -> Vec<Label> {
+    let decimals = options.decimals.unwrap_or_else(|| decimals(levels));
+    let mut candidates: Vec<Candidate> = levels
+        .iter()
+        .enumerate()
+        .filter(|(_, level)| level.kind != Some(ContourKind::Intermediate))
+        .flat_map(|(i, level)| {
+            let text = format!("{:.decimals$}", level.elevation);
+            level.paths.iter().enumerate().flat_map(move |(j, path)| {
+                candidates(i, j, path, &text, extent, options, interpolated)
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| a.score.total_cmp(&b.score));
+
+    let mut placed: Vec<(Candidate, (Point, Point))> = Vec::new();
+    for candidate in candidates {
+        let bounds = candidate.label.bounds(options);
+        let clear = placed.iter().all(|(other, other_bounds)| {
+            let (a, b) = (&other.label, &candidate.label);
+            let gap = (other.position - candidate.position).abs();
+            let gap = candidate.ring.map_or(gap, |length| gap.min(length - gap));
+            let too_close = a.level == b.level && a.path == b.path && gap < options.spacing;
+            !too_close && !overlaps(bounds, *other_bounds)
+        });
+        if clear {
+            placed.push((candidate, bounds));
+        }
+    }
+    placed
+        .into_iter()
+        .map(|(candidate, _)| candidate.label)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: (u32, u32) = (1000, 1000);
+
+    fn level(elevation: f32, paths: Vec<Path>) -> ContourLevel {
+        ContourLevel {
+            elevation,
+            paths,
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn decimals_test() {
+        let levels = |elevations: &[f32]| -> Vec<ContourLevel> {
+            elevations.iter().map(|&e| level(e, Vec::new())).collect()
+        };
+        assert_eq!(0, decimals(&levels(&[88.35294, 176.70588, 265.0588])));
+        assert_eq!(1, decimals(&levels(&[0.5, 1.0, 1.5])));
+        assert_eq!(2, decimals(&levels(&[1.0, 1.2, 1.25])));
+        assert_eq!(0, decimals(&levels(&[])));
+        assert_eq!(0, decimals(&levels(&[100.0])));
+        assert_eq!(1, decimals(&levels(&[0.5])));
+
+        // Labels are rounded to the decimals.
+        let levels = [
+            level(
+                88.35294,
+                vec![Path::from_xy(&[(0.0, 10.0), (500.0, 10.0)], false)],
+            ),
+            level(176.70588, Vec::new()),
+        ];
+        let labels = place_labels(&levels, EXTENT, &LabelOptions::default(), true);
+        assert_eq!("88", labels[0].text);
+        let options = LabelOptions {
+            decimals: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            "88.35",
+            place_labels(&levels, EXTENT, &options, true)[0].text
+        );
+    }
+
+    #[test]
+    fn straight_test() {
+        // A line running right to left with a sharp bend near its start,
+        // labelled once on the straight upright and away from the bend.
+        let options = LabelOptions {
+            font_size: 10.0,
+            spacing: 1000.0,
+            max_turn: 0.2,
+            max_deviation: 0.1,
+            ..Default::default()
+        };
+        let levels = [level(
+            100.0,
+            vec![Path::from_xy(
+                &[(100.0, 0.0), (100.0, 20.0), (10.0, 20.0)],
+                false,
+            )],
+        )];
+        let labels = place_labels(&levels, EXTENT, &options, true);
+
+        assert_eq!(1, labels.len());
+        let label = &labels[0];
+        assert_eq!("100", label.text);
+        assert_eq!(0.0, label.angle);
+        assert!(label.baseline.iter().all(|p| p.y == 20.0));
+        assert!(label.baseline[0].x < label.baseline[1].x);
+        // 3 digits and a margin take 28 pixels.
+        assert_eq!(28.0, label.baseline[1].x - label.baseline[0].x);
+        assert!(label.centre.x <= 100.0 - 14.0);
+
+        // Nothing goes along the border.
+        let levels = [level(
+            100.0,
+            vec![Path::from_xy(&[(0.0, 0.0), (0.0, 500.0)], false)],
+        )];
+        assert!(place_labels(&levels, EXTENT, &options, true).is_empty());
+    }
+
+    #[test]
+    fn spacing_test() {
+        let options = LabelOptions {
+            font_size: 10.0,
+            spacing: 100.0,
+            max_turn: 0.2,
+            max_deviation: 0.1,
+            ..Default::default()
+        };
+        let levels = [level(
+            5.0,
+            vec![Path::from_xy(&[(10.0, 10.0), (510.0, 10.0)], false)],
+        )];
+        let labels = place_labels(&levels, EXTENT, &options, true);
+        assert!(labels.len() >= 4 && labels.len() <= 5);
+        for (i, a) in labels.iter().enumerate() {
+            for b in &labels[i + 1..] {
+                assert!((a.centre.x - b.centre.x).abs() >= 100.0);
+            }
+        }
+    }
+
+    #[test]
+    fn ring_spacing_test() {
+        // Labels either side of where a ring starts are close around it.
+        let options = LabelOptions {
+            font_size: 10.0,
+            spacing: 300.0,
+            max_turn: 0.2,
+            max_deviation: 0.1,
+            ..Default::default()
+        };
+        let square = [
+            (100.0, 100.0),
+            (300.0, 100.0),
+            (300.0, 300.0),
+            (100.0, 300.0),
+            (100.0, 100.0),
+        ];
+        let levels = [level(5.0, vec![Path::from_xy(&square, true)])];
+        assert_eq!(2, place_labels(&levels, EXTENT, &options, true).len());
+    }
+
+    #[test]
+    fn overlap_test() {
+        // Two levels running right next to each other only fit one label
+        // where they're close, and intermediate contours get none.
+        let options = LabelOptions {
+            font_size: 10.0,
+            spacing: 1000.0,
+            max_turn: 0.2,
+            max_deviation: 0.1,
+            ..Default::default()
+        };
+        let mut levels = vec![
+            level(
+                1.0,
+                vec![Path::from_xy(&[(10.0, 10.0), (50.0, 10.0)], false)],
+            ),
+            level(
+                2.0,
+                vec![Path::from_xy(&[(10.0, 15.0), (50.0, 15.0)], false)],
+            ),
+        ];
+        assert_eq!(1, place_labels(&levels, EXTENT, &options, true).len());
+
+        levels[1].paths[0] = Path::from_xy(&[(10.0, 60.0), (50.0, 60.0)], false);
+        assert_eq!(2, place_labels(&levels, EXTENT, &options, true).len());
+
+        levels[1].kind = Some(ContourKind::Intermediate);
+        assert_eq!(1, place_labels(&levels, EXTENT, &options, true).len());
+    }
+}
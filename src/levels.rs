@@ -0,0 +1,306 @@
+//! Choosing the thresholds to contour a field at.
+//!
+//! Levels only make contours when some valid sample is above them and some
+//! isn't, so the generated ones all lie from the lowest value up to just
+//! under the highest. Explicit lists are taken as they are.
+
+use crate::{
+    error::{Error, Result, Warning},
+    marching_squares::{classify_levels, ContourLevel, Field},
+    util::Mode,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Spacing {
+    Interval(f32),
+    List(Vec<f32>),
+    Count(usize),
+    Quantiles(usize),
+    Logarithmic(usize),
+}
+
+/// How to pick the levels of a field, made with one of the spacings and
+/// adjusted with the `with_` methods.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Levels {
+    spacing: Spacing,
+    base: f32,
+    index_every: Option<usize>,
+}
+
+impl Levels {
+    fn new(spacing: Spacing) -> Self {
+        Levels {
+            spacing,
+            base: 0.0,
+            index_every: None,
+        }
+    }
+
+    /// Every multiple of `interval` from the base, which is 0 unless set
+    /// with [`Levels::with_base`].
+    pub fn interval(interval: f32) -> Self {
+        Levels::new(Spacing::Interval(interval))
+    }
+
+    /// Exactly these levels, sorted and without repeats.
+    pub fn list(levels: impl Into<Vec<f32>>) -> Self {
+        Levels::new(Spacing::List(levels.into()))
+    }
+
+    /// `count` levels evenly spaced between the lowest and highest values,
+    /// leaving out both of them.
+    pub fn count(count: usize) -> Self {
+        Levels::new(Spacing::Count(count))
+    }
+
+    /// The `count` breaks that split the valid samples into `count + 1`
+    /// groups of the same size, so that every band between levels covers
+    /// about as much of the field. Breaks that fall on the same value, as
+    /// on flat ground, are only used once.
+    pub fn quantiles(count: usize) -> Self {
+        Levels::new(Spacing::Quantiles(count))
+    }
+
+    /// `count` levels in geometric progression between the lowest and
+    /// highest values, leaving out both of them, so they get closer
+    /// together towards the lowest. Fields with values of zero or below are
+    /// shifted up to have a lowest value of 1 first.
+    pub fn logarithmic(count: usize) -> Self {
+        Levels::new(Spacing::Logarithmic(count))
+    }
+
+    /// Anchors an interval, and the counting of index contours along it.
+    pub fn with_base(mut self, base: f32) -> Self {
+        self.base = base;
+        self
+    }
+
+    /// Makes every `index_every`th level an index contour when classifying.
+    /// Along an interval they're the multiples of `index_every` intervals
+    /// from the base, otherwise they're counted from the lowest level.
+    pub fn with_index_every(mut self, index_every: usize) -> Self {
+        self.index_every = Some(index_every);
+        self
+    }
+
+    /// The levels for the field, in ascending order.
+    pub fn thresholds(&self, field: &Field) -> Result<Vec<f32>> {
+        match self.spacing {
+            Spacing::Interval(interval) if interval.is_nan() || interval <= 0.0 => {
+                return Err(Error::InvalidInterval { interval })
+            }
+            Spacing::Count(0) | Spacing::Quantiles(0) | Spacing::Logarithmic(0) => {
+                return Err(Error::InvalidLevelCount)
+            }
+            _ => (),
+        }
+
+        let (lowest, highest) = field.range();
+        // Fractions of the way from the lowest to the highest value.
+        let fractions = |count: usize| (1..=count).map(move |i| i as f64 / (count + 1) as f64);
+        let (lo, hi) = (lowest as f64, highest as f64);
+        let mut levels: Vec<f32> = match &self.spacing {
+            Spacing::List(levels) => {
+                let mut levels = levels.clone();
+                levels.sort_by(f32::total_cmp);
+                levels.dedup();
+                return Ok(levels);
+            }
+            _ if lowest >= highest => return Ok(Vec::new()),
+            &Spacing::Interval(interval) => {
+                // Counting whole intervals from the base keeps fractional
+                // intervals from drifting.
+                let first = ((lowest - self.base) / interval).ceil() as i64;
+                (first..)
+                    .map(|i| self.base + i as f32 * interval)
+                    .take_while(|&level| level < highest)
+                    .collect()
+            }
+            &Spacing::Count(count) => fractions(count)
+                .map(|t| (lo + (hi - lo) * t) as f32)
+                .collect(),
+            &Spacing::Logarithmic(count) => {
+                let shift = if lo > 0.0 { 0.0 } else { 1.0 - lo };
+                let (lo, hi) = (lo + shift, hi + shift);
+                fractions(count)
+                    .map(|t| (lo * (hi / lo).powf(t) - shift) as f32)
+                    .collect()
+            }
+            &Spacing::Quantiles(count) => {
+                let mut vals: Vec<f32> = field
+                    .vals
+                    .iter()
+                    .copied()
+                    .filter(|&v| field.is_valid(v))
+                    .collect();
+                vals.sort_unstable_by(f32::total_cmp);
+                let last = (vals.len() - 1) as f64;
+                fractions(count)
+                    .map(|t| {
+                        let at = t * last;
+                        let (below, above) = (vals[at.floor() as usize], vals[at.ceil() as usize]);
+                        (below as f64 + (above - below) as f64 * at.fract()) as f32
+                    })
+                    .collect()
+            }
+        };
+        levels.retain(|&level| level < highest);
+        levels.dedup();
+        Ok(levels)
+    }
+
+    /// Tags the levels as index or intermediate contours, if an index
+    /// spacing was set.
+    pub fn classify(&self, levels: &mut [ContourLevel]) {
+        let Some(index_every) = self.index_every else {
+            return;
+        };
+        let interval = match self.spacing {
+            Spacing::Interval(interval) => Some(interval),
+            _ => None,
+        };
+        classify_levels(levels, self.base, interval, index_every);
+    }
+
+    /// Traces the contours of the field at these levels, classified if an
+    /// index spacing was set.
+    pub fn contours(
+        &self,
+        field: &Field,
+        close_edges: bool,
+        mode: Mode,
+    ) -> Result<(Vec<ContourLevel>, Vec<Warning>)> {
+        let (mut levels, warnings) = field.contours(&self.thresholds(field)?, close_edges, mode)?;
+        self.classify(&mut levels);
+        Ok((levels, warnings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ContourKind;
+
+    fn ramp(from: f32, to: f32) -> Field {
+        let vals = (0..=10)
+            .map(|i| from + (to - from) * i as f32 / 10.0)
+            .collect();
+        Field::new((11, 1), vals).unwrap()
+    }
+
+    #[test]
+    fn interval_test() {
+        // Negative and fractional values, with the base below the data.
+        let field = ramp(-1.25, 0.5);
+        assert_eq!(
+            vec![-1.2, -0.9, -0.6, -0.3, 0.0, 0.3],
+            Levels::interval(0.3)
+                .with_base(-3.0)
+                .thresholds(&field)
+                .unwrap()
+                .iter()
+                .map(|l| (l * 10.0).round() / 10.0)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![-1.0, 0.0],
+            Levels::interval(1.0).thresholds(&field).unwrap()
+        );
+        // The lowest value gets a level, the highest doesn't.
+        assert_eq!(
+            vec![0.0, 5.0],
+            Levels::interval(5.0).thresholds(&ramp(0.0, 10.0)).unwrap()
+        );
+        assert!(Levels::interval(0.0).thresholds(&field).is_err());
+        assert!(Levels::interval(-1.0).thresholds(&field).is_err());
+    }
+
+    #[test]
+    fn list_test() {
+        assert_eq!(
+            vec![-2.0, 1.5, 100.0],
+            Levels::list([1.5, 100.0, -2.0, 1.5])
+                .thresholds(&ramp(0.0, 1.0))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn count_test() {
+        assert_eq!(
+            vec![-10.0, 0.0, 10.0],
+            Levels::count(3).thresholds(&ramp(-20.0, 20.0)).unwrap()
+        );
+        // A field that's all the same has nothing to contour.
+        let flat = Field::new((2, 2), vec![3.0; 4]).unwrap();
+        assert!(Levels::count(3).thresholds(&flat).unwrap().is_empty());
+        assert!(Levels::count(0).thresholds(&flat).is_err());
+    }
+
+    #[test]
+    fn quantiles_test() {
+        // Most of the field is low, so the breaks bunch up at the bottom.
+        let mut vals = vec![0.0; 6];
+        vals.extend([1.0, 2.0, 50.0, 100.0]);
+        let field = Field::new((5, 2), vals).unwrap();
+        assert_eq!(
+            vec![0.0, 0.4, 11.6],
+            Levels::quantiles(4).thresholds(&field).unwrap()
+        );
+
+        // Samples without data are left out.
+        let field = Field::new((5, 1), vec![-9999.0, 1.0, 2.0, 3.0, 4.0])
+            .unwrap()
+            .with_nodata(-9999.0);
+        assert_eq!(vec![2.5], Levels::quantiles(1).thresholds(&field).unwrap());
+    }
+
+    #[test]
+    fn logarithmic_test() {
+        let levels = Levels::logarithmic(2)
+            .thresholds(&ramp(1.0, 1000.0))
+            .unwrap();
+        assert_eq!(2, levels.len());
+        assert!((levels[0] - 10.0).abs() < 1e-3 && (levels[1] - 100.0).abs() < 1e-2);
+
+        // Zero and below are shifted up to start at 1.
+        let levels = Levels::logarithmic(2)
+            .thresholds(&ramp(-1.0, 998.0))
+            .unwrap();
+        assert!((levels[0] - 8.0).abs() < 1e-3 && (levels[1] - 98.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn classify_test() {
+        let field = ramp(0.0, 100.0);
+        let index = |levels: &Levels| -> Vec<f32> {
+            levels
+                .contours(&field, false, Mode::Strict)
+                .unwrap()
+                .0
+                .iter()
+                .filter(|l| l.kind == Some(ContourKind::Index))
+                .map(|l| l.elevation)
+                .collect()
+        };
+        assert_eq!(
+            vec![0.0, 50.0],
+            index(&Levels::interval(10.0).with_index_every(5))
+        );
+        assert_eq!(
+            vec![5.0, 55.0],
+            index(&Levels::interval(10.0).with_base(5.0).with_index_every(5))
+        );
+        assert_eq!(
+            vec![20.0, 60.0],
+            index(&Levels::count(4).with_index_every(2))
+        );
+        assert!(Levels::count(4)
+            .contours(&field, false, Mode::Strict)
+            .unwrap()
+            .0
+            .iter()
+            .all(|l| l.kind.is_none()));
+    }
+}
@@ -0,0 +1,39 @@
+//! Contour lines from gridded elevation data.
+//!
+//! Build a [`Field`] from a raster, extract the [`Path`]s for one or more
+//! thresholds with [`Field::layer_paths`] or [`Field::contours`], then write
+//! them out with one of the serializers such as [`svg::write_svg`].
+
+pub mod ascii;
+pub mod band;
+mod boundary;
+pub mod dxf;
+pub mod error;
+pub mod features;
+pub mod filter;
+pub mod geo;
+pub mod geojson;
+pub mod labels;
+pub mod levels;
+pub mod marching_squares;
+pub mod plot;
+pub mod polygon;
+pub mod raw;
+pub mod rgb;
+pub mod shapefile;
+pub mod simplify;
+pub mod smooth;
+pub mod svg;
+pub mod trace;
+pub mod travel;
+pub mod util;
+
+pub use band::ContourBand;
+pub use error::{Error, Result, Warning};
+pub use geo::{GeoTransform, Geometry};
+pub use levels::Levels;
+pub use marching_squares::{
+    classify_levels, Cell, CellLine, CellSegment, ContourKind, ContourLevel, Field,
+};
+pub use polygon::{group_rings, Polygon};
+pub use util::{paths_from_lines, CloseEdges, Line, Mode, Path, Point, RichPoint};
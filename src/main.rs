@@ -1,64 +1,410 @@
-use image::io::Reader as ImageReader;
-//use rand::{thread_rng, Rng};
-
-mod marching_squares;
-use marching_squares::*;
-mod util;
-use util::*;
-
-fn main() {
-    let palette = [
-        "#ffd8ba", "#f7a983", "#f28a91", "#db3b5d", "#57253b", "#ac2925", "#ef692f", "#eca549",
-        "#3e88b7", "#4b3b9c", "#6a6c56", "#adac8e", "#fff4e0", "#cecfbf", "#939487", "#2b2b26",
-    ];
-
-    let img = ImageReader::open("maple_bay_square.tif")
-        .expect("Should exist")
-        .decode()
-        .expect("Should be valid")
-        .into_luma16();
-
-    let data = img
-        .as_flat_samples()
-        .image_slice()
-        .expect("Has data")
-        .iter()
-        .map(|&x| x as f32)
-        .collect();
-
-    let field = Field {
-        extent: (img.width(), img.height()),
-        vals: data,
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path as FsPath,
+    process::ExitCode,
+};
+
+use clap::Parser;
+use isolines::{
+    dxf::{self, DxfOptions},
+    features::FeatureFilter,
+    geo::esri_wkt,
+    geojson::{self, GeoJsonOptions},
+    labels::LabelOptions,
+    plot::{self, PlotOptions},
+    shapefile::{self, ShapefileOptions},
+    simplify::{self, SimplifyOptions, Tolerance},
+    smooth::{self, SmoothOptions},
+    svg::{self, SvgOptions, DEFAULT_PALETTE},
+    ContourKind, ContourLevel, Field, GeoTransform, Geometry, Levels, Mode,
+};
+
+mod cli;
+use cli::*;
+
+fn load_palette(path: Option<&FsPath>) -> Result<Vec<String>, Box<dyn Error>> {
+    let palette: Vec<String> = match path {
+        Some(path) => fs::read_to_string(path)?
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect(),
+        None => DEFAULT_PALETTE.iter().map(|&c| c.to_owned()).collect(),
+    };
+    if palette.is_empty() {
+        return Err("Palette has no colours".into());
+    }
+    Ok(palette)
+}
+
+fn open_field(args: &ContourArgs) -> Result<Field, Box<dyn Error>> {
+    let mut field = match args.encoding {
+        Some(encoding) => Field::open_rgb(&args.input, encoding)?,
+        None => Field::open(&args.input)?,
+    };
+    if let Some(tile) = &args.tile {
+        let &[z, x, y] = tile.as_slice() else {
+            return Err("A tile is given by its position, Z/X/Y".into());
+        };
+        let transform = GeoTransform::xyz_tile(z, x, y, field.extent);
+        field = field.with_transform(transform).with_crs(3857);
+    }
+    if let Some(nodata) = args.nodata {
+        field = field.with_nodata(nodata);
+    }
+    if let Some(radius) = args.median {
+        field = field.median_filter(radius);
+    }
+    if let Some(sigmas) = &args.bilateral {
+        let &[space, range] = sigmas.as_slice() else {
+            return Err("The bilateral filter takes two sigmas, SPACE,RANGE".into());
+        };
+        field = field.bilateral_filter(space, range);
+    }
+    if let Some(sigma) = args.gaussian {
+        field = field.gaussian_blur(sigma);
+    }
+    Ok(field)
+}
+
+fn mode(args: &ContourArgs) -> Mode {
+    if args.strict {
+        Mode::Strict
+    } else {
+        Mode::Lenient
+    }
+}
+
+// Buffered writer for a file, or stdout when there's no path.
+fn output(path: Option<&FsPath>) -> io::Result<BufWriter<Box<dyn Write>>> {
+    Ok(BufWriter::new(match path {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    }))
+}
+
+fn levels(args: &ContourArgs, default_count: usize) -> Levels {
+    let levels = if let Some(levels) = &args.levels {
+        Levels::list(levels.clone())
+    } else if let Some(interval) = args.interval {
+        Levels::interval(interval)
+    } else if let Some(count) = args.quantiles {
+        Levels::quantiles(count)
+    } else if let Some(count) = args.logarithmic {
+        Levels::logarithmic(count)
+    } else {
+        Levels::count(args.count.unwrap_or(default_count))
+    };
+    let levels = levels.with_base(args.base);
+    match args.index_every {
+        Some(index_every) => levels.with_index_every(index_every),
+        None => levels,
+    }
+}
+
+fn no_levels(field: &Field) -> Box<dyn Error> {
+    let (lowest, highest) = field.range();
+    format!("No levels between the lowest value {lowest} and the highest {highest}").into()
+}
+
+fn thresholds(field: &Field, levels: &Levels) -> Result<Vec<f32>, Box<dyn Error>> {
+    let thresholds = levels.thresholds(field)?;
+    if thresholds.is_empty() {
+        return Err(no_levels(field));
+    }
+    Ok(thresholds)
+}
+
+// Contours of the field without the features under the size limits,
+// simplified and smoothed when asked to.
+fn contours(
+    field: &Field,
+    args: &ContourArgs,
+    post_process: &PostProcessArgs,
+    strategy: &Levels,
+    close_edges: bool,
+    interpolated: bool,
+) -> Result<Vec<ContourLevel>, Box<dyn Error>> {
+    let (mut levels, warnings) = strategy.contours(field, close_edges, mode(args))?;
+    if levels.is_empty() {
+        return Err(no_levels(field));
+    }
+    for warning in warnings {
+        eprintln!("warning: {warning}");
+    }
+    let filter = FeatureFilter {
+        min_area: post_process.min_area,
+        min_length: post_process.min_length,
+        min_size: post_process.min_size,
+        interpolated,
+    };
+    for removed in filter.apply(&mut levels) {
+        if removed.total() > 0 {
+            eprintln!(
+                "level {}: removed {} pimples, {} pits and {} stubs",
+                removed.elevation, removed.pimples, removed.pits, removed.stubs
+            );
+        }
+    }
+    if let Some(tolerance) = post_process.simplify {
+        let tolerance = if post_process.simplify_map_units {
+            if field.transform.is_none() {
+                return Err(format!("{} isn't georeferenced", args.input.display()).into());
+            }
+            Tolerance::MapUnits(tolerance)
+        } else {
+            Tolerance::Pixels(tolerance as f32)
+        };
+        let options = SimplifyOptions {
+            method: post_process.simplify_method,
+            tolerance,
+            interpolated,
+            transform: field.transform,
+        };
+        simplify::simplify_levels(&mut levels, &options);
+    }
+    if let Some(method) = post_process.smooth {
+        let options = SmoothOptions {
+            method,
+            steps: post_process.smooth_steps,
+            extent: field.extent,
+        };
+        smooth::smooth_levels(&mut levels, &options);
+    }
+    Ok(levels)
+}
+
+fn svg(args: &SvgArgs) -> Result<(), Box<dyn Error>> {
+    let field = open_field(&args.contour)?;
+    if args.map_units && field.transform.is_none() {
+        return Err(format!("{} isn't georeferenced", args.contour.input.display()).into());
+    }
+    let options = SvgOptions {
+        palette: load_palette(args.palette.as_deref())?,
+        geometry: Geometry {
+            interpolated: !args.raw,
+            transform: field.transform.filter(|_| args.map_units),
+        },
+        curves: args.curves,
+        index_width: args.index_width,
+        labels: args.labels.then(|| LabelOptions {
+            font_size: args.label_size,
+            spacing: args.label_spacing,
+            decimals: args.label_decimals,
+            ..Default::default()
+        }),
+    };
+    // One more band than there are levels, so each band gets its own colour.
+    let default_count = if args.bands {
+        options.palette.len().saturating_sub(1).max(1)
+    } else {
+        options.palette.len()
+    };
+    let strategy = levels(&args.contour, default_count);
+
+    let mut out = output(args.output.as_deref())?;
+    if args.bands {
+        let bands = field.bands(&thresholds(&field, &strategy)?)?;
+        svg::write_bands_svg(&mut out, field.extent, &bands, &options)?;
+    } else {
+        let levels = contours(
+            &field,
+            &args.contour,
+            &args.post_process,
+            &strategy,
+            !args.no_close_edges,
+            !args.raw,
+        )?;
+        svg::write_svg(&mut out, field.extent, &levels, &options)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn geojson(args: &GeoJsonArgs) -> Result<(), Box<dyn Error>> {
+    let field = open_field(&args.contour)?;
+    let options = GeoJsonOptions {
+        geometry: Geometry {
+            interpolated: !args.raw,
+            transform: field.transform,
+        },
     };
+    let strategy = levels(&args.contour, DEFAULT_PALETTE.len());
+
+    let mut out = output(args.output.as_deref())?;
+    if args.bands {
+        let bands = field.bands(&thresholds(&field, &strategy)?)?;
+        geojson::write_bands_geojson(&mut out, &bands, &options)?;
+    } else {
+        let levels = contours(
+            &field,
+            &args.contour,
+            &args.post_process,
+            &strategy,
+            !args.no_close_edges,
+            !args.raw,
+        )?;
+        geojson::write_geojson(&mut out, &levels, &options)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn shapefile(args: &ShapefileArgs) -> Result<(), Box<dyn Error>> {
+    let field = open_field(&args.contour)?;
+    let options = ShapefileOptions {
+        geometry: Geometry {
+            interpolated: !args.raw,
+            transform: field.transform,
+        },
+        ..Default::default()
+    };
+    let strategy = levels(&args.contour, DEFAULT_PALETTE.len());
+
+    let mut shp = output(Some(&args.output.with_extension("shp")))?;
+    let mut shx = output(Some(&args.output.with_extension("shx")))?;
+    let mut dbf = output(Some(&args.output.with_extension("dbf")))?;
+    if args.polygons {
+        let bands = field.bands(&thresholds(&field, &strategy)?)?;
+        shapefile::write_polygons(&mut shp, &mut shx, &mut dbf, &bands, &options)?;
+    } else {
+        let levels = contours(
+            &field,
+            &args.contour,
+            &args.post_process,
+            &strategy,
+            !args.no_close_edges,
+            !args.raw,
+        )?;
+        shapefile::write_polylines(&mut shp, &mut shx, &mut dbf, &levels, &options)?;
+    }
+    for mut out in [shp, shx, dbf] {
+        out.flush()?;
+    }
 
-    println!(
-        "<svg width=\"{}\" height=\"{}\" version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\">",
-        img.width(),
-        img.height()
-    );
-
-    let highest = field
-        .vals
-        .iter()
-        .reduce(|biggest, f| if f > biggest { f } else { biggest })
-        .unwrap()
-        .to_owned();
-
-    let num_lines = palette.len();
-    let step = (highest as usize) / num_lines;
-    for (i, threshold) in (0..highest as usize).step_by(step).enumerate() {
-        let colour = palette[(palette.len() - 1) - (i % palette.len())];
-        println!("<g stroke=\"{colour}\" stroke-width=\"1\" fill=\"none\" >");
-        let paths = field.layer_paths(threshold as f32, true);
-        for path in paths.iter().filter(|p| p.points.len() > 2) {
-            let fill = if path.closed { colour } else { "none" };
-            println!("<path fill=\"{fill}\" d=\"{}\" />", path.to_svg(true));
+    match field.crs.map(|epsg| (epsg, esri_wkt(epsg))) {
+        Some((_, Some(wkt))) => fs::write(args.output.with_extension("prj"), wkt)?,
+        Some((epsg, None)) => eprintln!("warning: no projection file for EPSG:{epsg}"),
+        None => (),
+    }
+    Ok(())
+}
+
+fn dxf(args: &DxfArgs) -> Result<(), Box<dyn Error>> {
+    let field = open_field(&args.contour)?;
+    let options = DxfOptions {
+        geometry: Geometry {
+            interpolated: !args.raw,
+            transform: field.transform,
+        },
+        index_width: args.index_width,
+    };
+    let strategy = levels(&args.contour, DEFAULT_PALETTE.len());
+
+    let levels = contours(
+        &field,
+        &args.contour,
+        &args.post_process,
+        &strategy,
+        !args.no_close_edges,
+        !args.raw,
+    )?;
+    let mut out = output(args.output.as_deref())?;
+    dxf::write_dxf(&mut out, field.extent, &levels, &options)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn plot(args: &PlotArgs) -> Result<(), Box<dyn Error>> {
+    let field = open_field(&args.contour)?;
+    let options = PlotOptions {
+        format: args.format,
+        palette: load_palette(args.palette.as_deref())?,
+        geometry: Geometry {
+            interpolated: !args.raw,
+            transform: None,
+        },
+        paper: args.paper,
+        margin: args.margin,
+        scale: args.scale,
+        feed_rate: args.feed_rate,
+        pen_up: args.pen_up.clone(),
+        pen_down: args.pen_down.clone(),
+    };
+    if options.margin * 2.0 >= options.paper.width.min(options.paper.height) {
+        return Err(format!(
+            "A {}mm margin leaves no room on {} paper",
+            options.margin, options.paper
+        )
+        .into());
+    }
+    let strategy = levels(&args.contour, options.palette.len());
+
+    let levels = contours(
+        &field,
+        &args.contour,
+        &args.post_process,
+        &strategy,
+        !args.no_close_edges,
+        !args.raw,
+    )?;
+    let mut pens = plot::pens(field.extent, &levels, &options);
+    if !args.keep_order {
+        let travel = plot::optimise_travel(&mut pens, !args.no_reverse);
+        for (pen, (before, after)) in pens.iter().zip(travel) {
+            eprintln!(
+                "pen {} {}{}: {:.0}mm of travel, down from {:.0}mm",
+                pen.number,
+                pen.colour,
+                if pen.index { " index" } else { "" },
+                after,
+                before
+            );
+        }
+    }
+    let mut out = output(args.output.as_deref())?;
+    plot::write_plot(&mut out, &pens, &options)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Svg(args) => svg(&args),
+        Command::Geojson(args) => geojson(&args),
+        Command::Shapefile(args) => shapefile(&args),
+        Command::Dxf(args) => dxf(&args),
+        Command::Plot(args) => plot(&args),
+        Command::Levels(args) => {
+            let field = open_field(&args)?;
+            let strategy = levels(&args, DEFAULT_PALETTE.len());
+            let mut levels: Vec<ContourLevel> = thresholds(&field, &strategy)?
+                .into_iter()
+                .map(|elevation| ContourLevel {
+                    elevation,
+                    paths: Vec::new(),
+                    kind: None,
+                })
+                .collect();
+            strategy.classify(&mut levels);
+            for level in levels {
+                match level.kind {
+                    Some(ContourKind::Index) => println!("{} index", level.elevation),
+                    _ => println!("{}", level.elevation),
+                }
+            }
+            Ok(())
         }
-        println!("</g>");
     }
+}
 
-    //let threshold = 47.0;
-    //let paths = field.layer_paths(threshold);
-    //paths.iter().for_each(|path| println!("{}", path.to_svg()));
-    println!("</svg>");
+fn main() -> ExitCode {
+    match run(Cli::parse().command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
 }
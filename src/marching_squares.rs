@@ -1,4 +1,13 @@
-use crate::{paths_from_lines, util::*};
+use std::path::Path as FsPath;
+
+use image::{io::Reader as ImageReader, ImageError};
+
+use crate::{
+    boundary::Boundary,
+    error::{Error, Result, Warning},
+    geo::GeoTransform,
+    util::*,
+};
 
 #[derive(Debug)]
 pub struct Cell {
@@ -25,43 +34,201 @@ pub enum CellSegment {
 pub struct Field {
     pub extent: (u32, u32),
     pub vals: Vec<f32>,
+    /// Value marking samples with no data. NaN samples are never valid either,
+    /// so they can be used as a mask.
+    pub nodata: Option<f32>,
+    /// Where the samples are on the map, if the raster is georeferenced.
+    pub transform: Option<GeoTransform>,
+    /// EPSG code of the coordinate reference system the transform maps into.
+    pub crs: Option<u32>,
+}
+
+/// Whether a contour is one of the heavier index contours on a map or one of
+/// the intermediate contours between them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContourKind {
+    Index,
+    Intermediate,
+}
+
+impl ContourKind {
+    /// Whether the level at `elevation` is an index contour, being every
+    /// `index_every`th multiple of `interval` above or below `base`. Levels
+    /// off the interval are intermediate.
+    pub fn of(elevation: f32, base: f32, interval: f32, index_every: usize) -> Self {
+        let steps = (elevation - base) / interval;
+        let step = steps.round();
+        let on_interval = (steps - step).abs() < 1e-3;
+        if index_every > 0 && on_interval && (step as i64).rem_euclid(index_every as i64) == 0 {
+            ContourKind::Index
+        } else {
+            ContourKind::Intermediate
+        }
+    }
+}
+
+/// Tags every level as an index or intermediate contour. With an interval
+/// the index contours are every `index_every`th multiple of it from `base`,
+/// as on a map, and without one they're every `index_every`th level
+/// counting from the lowest.
+pub fn classify_levels(
+    levels: &mut [ContourLevel],
+    base: f32,
+    interval: Option<f32>,
+    index_every: usize,
+) {
+    for (i, level) in levels.iter_mut().enumerate() {
+        level.kind = Some(match interval {
+            Some(interval) if interval > 0.0 => {
+                ContourKind::of(level.elevation, base, interval, index_every)
+            }
+            _ if index_every > 0 && i % index_every == 0 => ContourKind::Index,
+            _ => ContourKind::Intermediate,
+        });
+    }
+}
+
+/// The paths traced for a single threshold.
+#[derive(Debug, Clone)]
+pub struct ContourLevel {
+    pub elevation: f32,
+    pub paths: Vec<Path>,
+    /// Unset unless the levels have been classified.
+    pub kind: Option<ContourKind>,
 }
 
 impl Field {
-    pub fn val_at(&self, pos: (u32, u32)) -> f32 {
+    /// Creates a field from row-major values, `vals.len()` must be `width * height`.
+    pub fn new(extent: (u32, u32), vals: Vec<f32>) -> Result<Self> {
+        if extent.0 == 0 || extent.1 == 0 || vals.len() != extent.0 as usize * extent.1 as usize {
+            return Err(Error::InvalidExtent {
+                extent,
+                len: vals.len(),
+            });
+        }
+        Ok(Field {
+            extent,
+            vals,
+            nodata: None,
+            transform: None,
+            crs: None,
+        })
+    }
+
+    /// Loads the first channel of a greyscale raster such as a 16 bit DEM.
+    /// TIFFs are read with [`Field::open_geotiff`] to keep their georeferencing,
+    /// and other formats by their extension: `.asc` with
+    /// [`Field::open_ascii_grid`], `.xyz` with [`Field::open_xyz`], `.hgt`
+    /// with [`Field::open_hgt`], `.bil` with [`Field::open_bil`] and `.flt`
+    /// with [`Field::open_flt`].
+    pub fn open(path: impl AsRef<FsPath>) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        if extension.eq_ignore_ascii_case("tif") || extension.eq_ignore_ascii_case("tiff") {
+            return Field::open_geotiff(path);
+        }
+        match extension.to_ascii_lowercase().as_str() {
+            "asc" => return Field::open_ascii_grid(path),
+            "xyz" => return Field::open_xyz(path),
+            "hgt" => return Field::open_hgt(path),
+            "bil" => return Field::open_bil(path),
+            "flt" => return Field::open_flt(path),
+            _ => (),
+        }
+
+        let img = ImageReader::open(path)
+            .map_err(ImageError::IoError)?
+            .decode()?
+            .into_luma16();
+
+        let vals = img.as_raw().iter().map(|&x| x as f32).collect();
+
+        Field::new((img.width(), img.height()), vals)
+    }
+
+    /// Treats samples equal to `nodata` as missing. Cells touching a missing
+    /// sample aren't contoured, so contours end where they meet a void.
+    pub fn with_nodata(mut self, nodata: f32) -> Self {
+        self.nodata = Some(nodata);
+        self
+    }
+
+    pub(crate) fn is_valid(&self, val: f32) -> bool {
+        !val.is_nan() && Some(val) != self.nodata
+    }
+
+    // Whether all four corners of the cell at `pos` have data. The cell has to
+    // be inside the field.
+    pub(crate) fn valid_cell(&self, pos: (u32, u32)) -> bool {
+        let (x, y) = pos;
+        [(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)]
+            .iter()
+            .all(|&(x, y)| self.is_valid(self.sample(x, y)))
+    }
+
+    /// Lowest and highest valid value in the field.
+    pub fn range(&self) -> (f32, f32) {
+        self.vals
+            .iter()
+            .filter(|&&v| self.is_valid(v))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| {
+                (lo.min(v), hi.max(v))
+            })
+    }
+
+    // Unchecked version of `val_at` for positions that are known to be valid.
+    pub(crate) fn sample(&self, x: u32, y: u32) -> f32 {
+        self.vals[x as usize + y as usize * self.extent.0 as usize]
+    }
+
+    pub fn val_at(&self, pos: (u32, u32)) -> Result<f32> {
         let (x, y) = pos;
-        if x > self.extent.0 - 1 || y > self.extent.1 - 1 {
-            panic!("Invalid coords");
+        if x >= self.extent.0 || y >= self.extent.1 {
+            return Err(Error::InvalidCoordinates {
+                pos,
+                extent: self.extent,
+            });
         }
-        let i = x + y * self.extent.0;
-        self.vals[i as usize]
+        let i = x as usize + y as usize * self.extent.0 as usize;
+        Ok(self.vals[i])
     }
 
-    pub fn cell_at(&self, threshold: f32, pos: (u32, u32)) -> Cell {
+    pub fn cell_at(&self, threshold: f32, pos: (u32, u32)) -> Result<Cell> {
         let (x, y) = pos;
-        if x > self.extent.0 - 2 || y > self.extent.1 - 2 {
-            panic!("Invalid cell coords");
+        if x + 1 >= self.extent.0 || y + 1 >= self.extent.1 {
+            return Err(Error::InvalidCoordinates {
+                pos,
+                extent: self.extent,
+            });
+        }
+
+        if !self.valid_cell(pos) {
+            return Ok(Cell {
+                pos,
+                id: 0,
+                segment: CellSegment::Zero,
+            });
         }
 
         let vals = [
-            self.val_at((x, y)),
-            self.val_at((x + 1, y)),
-            self.val_at((x, y + 1)),
-            self.val_at((x + 1, y + 1)),
+            self.val_at((x, y))?,
+            self.val_at((x + 1, y))?,
+            self.val_at((x, y + 1))?,
+            self.val_at((x + 1, y + 1))?,
         ];
 
         let id = id_from_vals(threshold, &vals);
 
         let segment = cell_segment(threshold, pos, id, &vals);
 
-        Cell { pos, id, segment }
+        Ok(Cell { pos, id, segment })
     }
 
-    pub fn raw_lines(&self, threshold: f32) -> Vec<CellLine> {
+    pub fn raw_lines(&self, threshold: f32) -> Result<Vec<CellLine>> {
         let mut lines = Vec::new();
         for y in 0..(self.extent.1 - 1) {
             for x in 0..(self.extent.0 - 1) {
-                let cell = self.cell_at(threshold, (x, y));
+                let cell = self.cell_at(threshold, (x, y))?;
                 match cell.segment {
                     CellSegment::Zero => (),
                     CellSegment::One(line) => {
@@ -75,23 +242,59 @@ impl Field {
             }
         }
 
-        lines
+        Ok(lines)
     }
 
-    pub fn layer_paths(&self, threshold: f32, close_edges: bool) -> Vec<Path> {
-        let lines = self.raw_lines(threshold);
-        paths_from_lines(
-            &lines,
-            if close_edges {
-                CloseEdges::ForExtent(self.extent.0, self.extent.1)
-            } else {
-                CloseEdges::None
-            },
-        )
+    pub fn layer_paths(
+        &self,
+        threshold: f32,
+        close_edges: bool,
+        mode: Mode,
+    ) -> Result<(Vec<Path>, Vec<Warning>)> {
+        let boundary = close_edges.then(|| self.boundary());
+        self.closed_layer_paths(threshold, boundary.as_ref(), mode)
+    }
+
+    fn closed_layer_paths(
+        &self,
+        threshold: f32,
+        boundary: Option<&Boundary>,
+        mode: Mode,
+    ) -> Result<(Vec<Path>, Vec<Warning>)> {
+        let (mut paths, warnings) = self.trace_paths(threshold, mode)?;
+        if let Some(boundary) = boundary {
+            boundary.close_paths(&mut paths, |(x, y)| self.sample(x, y) > threshold);
+        }
+        Ok((paths, warnings))
+    }
+
+    pub fn contours(
+        &self,
+        thresholds: &[f32],
+        close_edges: bool,
+        mode: Mode,
+    ) -> Result<(Vec<ContourLevel>, Vec<Warning>)> {
+        // The boundary is the same for every level.
+        let boundary = close_edges.then(|| self.boundary());
+        let mut warnings = Vec::new();
+        let levels = thresholds
+            .iter()
+            .map(|&elevation| {
+                let (paths, level_warnings) =
+                    self.closed_layer_paths(elevation, boundary.as_ref(), mode)?;
+                warnings.extend(level_warnings);
+                Ok(ContourLevel {
+                    elevation,
+                    paths,
+                    kind: None,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok((levels, warnings))
     }
 }
 
-fn id_from_vals(threshold: f32, vals: &[f32; 4]) -> u8 {
+pub(crate) fn id_from_vals(threshold: f32, vals: &[f32; 4]) -> u8 {
     vals.iter().fold(0, |id, &val| {
         (id << 1) + (if val > threshold { 1 } else { 0 })
     })
@@ -559,6 +762,37 @@ fn cell_segment(threshold: f32, pos: (u32, u32), id: u8, vals: &[f32; 4]) -> Cel
 mod tests {
     use super::*;
 
+    #[test]
+    fn classify_test() {
+        let mut levels: Vec<ContourLevel> = [-50.0, -25.0, 0.0, 25.0, 60.0, 75.0, 100.0]
+            .iter()
+            .map(|&elevation| ContourLevel {
+                elevation,
+                paths: Vec::new(),
+                kind: None,
+            })
+            .collect();
+        let index = |levels: &[ContourLevel]| -> Vec<f32> {
+            levels
+                .iter()
+                .filter(|l| l.kind == Some(ContourKind::Index))
+                .map(|l| l.elevation)
+                .collect()
+        };
+
+        // Every 4th multiple of 25 from 0, and 60 is off the interval.
+        classify_levels(&mut levels, 0.0, Some(25.0), 4);
+        assert_eq!(vec![0.0, 100.0], index(&levels));
+        assert!(levels.iter().all(|l| l.kind.is_some()));
+
+        // Anchored at a base of 50 everything below counts the same way.
+        classify_levels(&mut levels, 50.0, Some(25.0), 2);
+        assert_eq!(vec![-50.0, 0.0, 100.0], index(&levels));
+
+        classify_levels(&mut levels, 0.0, None, 3);
+        assert_eq!(vec![-50.0, 25.0, 100.0], index(&levels));
+    }
+
     #[test]
     fn id_test() {
         let threshold = 5.0;
@@ -580,9 +814,15 @@ mod tests {
         let vals = [1.0, 3.0, 3.0, 7.0];
         let segment = cell_segment(threshold, (0, 0), 0b0001, &vals);
         assert_eq!(
-            CellSegment::One(Line {
-                start: Point { x: 0.5, y: 1.0 },
-                end: Point { x: 1.0, y: 0.5 }
+            CellSegment::One(CellLine {
+                interpolated_line: Line {
+                    start: Point { x: 0.5, y: 1.0 },
+                    end: Point { x: 1.0, y: 0.5 }
+                },
+                raw_line: Line {
+                    start: Point { x: 0.5, y: 1.0 },
+                    end: Point { x: 1.0, y: 0.5 }
+                },
             }),
             segment
         );
@@ -590,11 +830,96 @@ mod tests {
         let vals = [9.0, 7.0, 7.0, 3.0];
         let segment = cell_segment(threshold, (0, 0), 0b1110, &vals);
         assert_eq!(
-            CellSegment::One(Line {
-                start: Point { x: 1.0, y: 0.5 },
-                end: Point { x: 0.5, y: 1.0 },
+            CellSegment::One(CellLine {
+                interpolated_line: Line {
+                    start: Point { x: 1.0, y: 0.5 },
+                    end: Point { x: 0.5, y: 1.0 },
+                },
+                raw_line: Line {
+                    start: Point { x: 1.0, y: 0.5 },
+                    end: Point { x: 0.5, y: 1.0 },
+                },
             }),
             segment
         );
     }
+
+    #[test]
+    fn contours_test() {
+        #[rustfmt::skip]
+        let field = Field::new((3, 3), vec![
+            1.0, 1.0, 1.0,
+            1.0, 9.0, 1.0,
+            1.0, 1.0, 1.0,
+        ]).unwrap();
+        assert_eq!((1.0, 9.0), field.range());
+
+        let levels = field
+            .contours(&[0.0, 5.0, 10.0], false, Mode::Strict)
+            .unwrap()
+            .0;
+        assert_eq!(3, levels.len());
+        assert!(levels[0].paths.is_empty());
+        assert_eq!(1, levels[1].paths.len());
+        assert!(levels[1].paths[0].closed);
+        assert!(levels[2].paths.is_empty());
+    }
+
+    #[test]
+    fn closed_edges_test() {
+        // Closing every contour along the border covers exactly the area of
+        // the polygons above the threshold, including the whole field at -2
+        // where no contour reaches the border.
+        let vals = (0..64)
+            .map(|i| ((i % 8) as f32 * 0.9).sin() * ((i / 8) as f32 * 0.7).cos())
+            .collect();
+        let field = Field::new((8, 8), vals).unwrap();
+
+        for threshold in [-0.5, 0.0, 0.3, -2.0] {
+            let paths = field.layer_paths(threshold, true, Mode::Strict).unwrap().0;
+            assert!(paths.iter().all(|path| path.closed));
+            let area: f32 = paths.iter().map(|path| path.signed_area(true)).sum();
+
+            let polygons = field.level_polygons(threshold).unwrap();
+            let expected: f32 = polygons
+                .iter()
+                .flat_map(|polygon| std::iter::once(&polygon.exterior).chain(&polygon.holes))
+                .map(|ring| ring.signed_area(true))
+                .sum();
+            assert!((area - expected).abs() < 1e-3, "{area} != {expected}");
+        }
+    }
+
+    #[test]
+    fn nodata_test() {
+        let field = Field::new((2, 2), vec![-32768.0, 3.0, f32::NAN, 7.0])
+            .unwrap()
+            .with_nodata(-32768.0);
+        assert_eq!((3.0, 7.0), field.range());
+        assert_eq!(0, field.cell_at(5.0, (0, 0)).unwrap().id);
+        assert!(field
+            .layer_paths(5.0, true, Mode::Strict)
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_coords_test() {
+        let field = Field::new((2, 3), vec![0.0; 6]).unwrap();
+        assert_eq!(0.0, field.val_at((1, 2)).unwrap());
+        assert!(matches!(
+            field.val_at((2, 0)),
+            Err(Error::InvalidCoordinates { pos: (2, 0), .. })
+        ));
+        assert!(field.cell_at(0.0, (0, 1)).is_ok());
+        assert!(matches!(
+            field.cell_at(0.0, (1, 0)),
+            Err(Error::InvalidCoordinates { .. })
+        ));
+        assert!(matches!(
+            Field::new((2, 2), vec![0.0; 3]),
+            Err(Error::InvalidExtent { len: 3, .. })
+        ));
+    }
 }
@@ -0,0 +1,430 @@
+//! Pen plotter output, as HPGL or G-code with the contours scaled onto a
+//! sheet of paper in millimetres.
+
+use std::{
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use itertools::join;
+
+use crate::{
+    geo::Geometry,
+    marching_squares::{ContourKind, ContourLevel},
+    svg::DEFAULT_PALETTE,
+    travel,
+};
+
+/// HPGL plotter units per millimetre.
+const HPGL_UNITS: f64 = 40.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlotFormat {
+    Hpgl,
+    Gcode,
+}
+
+impl FromStr for PlotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hpgl" | "plt" => Ok(PlotFormat::Hpgl),
+            "gcode" | "g-code" | "nc" => Ok(PlotFormat::Gcode),
+            _ => Err(format!(
+                "Unknown plotter format {s}, expected hpgl or gcode"
+            )),
+        }
+    }
+}
+
+/// Width and height of a sheet in millimetres, as it lies on the plotter.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Paper {
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Paper {
+    pub const A3: Paper = Paper {
+        width: 297.0,
+        height: 420.0,
+    };
+    pub const A4: Paper = Paper {
+        width: 210.0,
+        height: 297.0,
+    };
+    pub const A5: Paper = Paper {
+        width: 148.0,
+        height: 210.0,
+    };
+    pub const LETTER: Paper = Paper {
+        width: 215.9,
+        height: 279.4,
+    };
+
+    pub fn landscape(self) -> Self {
+        Paper {
+            width: self.height,
+            height: self.width,
+        }
+    }
+}
+
+/// Parses a named size such as `a4` or `letter`, with an optional
+/// `-landscape` suffix, or explicit millimetres as `WIDTHxHEIGHT`.
+impl FromStr for Paper {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase();
+        let (name, landscape) = match name.strip_suffix("-landscape") {
+            Some(name) => (name, true),
+            None => (name.as_str(), false),
+        };
+        let paper = match name {
+            "a3" => Paper::A3,
+            "a4" => Paper::A4,
+            "a5" => Paper::A5,
+            "letter" => Paper::LETTER,
+            _ => {
+                let size = name.split_once('x').and_then(|(w, h)| {
+                    Some(Paper {
+                        width: w.trim().parse().ok()?,
+                        height: h.trim().parse().ok()?,
+                    })
+                });
+                match size {
+                    Some(paper) if paper.width > 0.0 && paper.height > 0.0 => paper,
+                    _ => return Err(format!("Unknown paper size {s}")),
+                }
+            }
+        };
+        Ok(if landscape { paper.landscape() } else { paper })
+    }
+}
+
+impl fmt::Display for Paper {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlotOptions {
+    pub format: PlotFormat,
+    /// Stroke colours, assigned to levels like the SVG palette. Every
+    /// distinct colour that's drawn gets a pen of its own, numbered from 1.
+    pub palette: Vec<String>,
+    /// Plots are laid out in pixels, so only `geometry.interpolated` is used
+    pub geometry: Geometry,
+    pub paper: Paper,
+    /// Blank space kept around every edge of the paper, in millimetres
+    pub margin: f64,
+    /// Millimetres per pixel, or as large as fits inside the margins if unset
+    pub scale: Option<f64>,
+    /// Drawing speed in millimetres per minute
+    pub feed_rate: f64,
+    /// G-code lifting the pen. HPGL always uses `PU`.
+    pub pen_up: String,
+    /// G-code lowering the pen. HPGL always uses `PD`.
+    pub pen_down: String,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        Self {
+            format: PlotFormat::Hpgl,
+            palette: DEFAULT_PALETTE.iter().map(|&c| c.to_owned()).collect(),
+            geometry: Geometry::default(),
+            paper: Paper::A4,
+            margin: 10.0,
+            scale: None,
+            feed_rate: 1500.0,
+            pen_up: "G0 Z5".to_owned(),
+            pen_down: "G0 Z0".to_owned(),
+        }
+    }
+}
+
+/// The strokes drawn with one pen, in millimetres from the bottom left
+/// corner of the paper.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pen {
+    pub number: usize,
+    pub colour: String,
+    /// Draws index contours, to be loaded with a heavier pen than the
+    /// intermediate ones of the same colour
+    pub index: bool,
+    pub strokes: Vec<Vec<(f64, f64)>>,
+}
+
+/// Scales the contours of a field with the given extent onto the paper,
+/// centred between the margins, and groups them by pen so that every pen
+/// is only picked up once. Index contours get pens of their own.
+pub fn pens(extent: (u32, u32), levels: &[ContourLevel], options: &PlotOptions) -> Vec<Pen> {
+    // Sample coordinates run from 0 to one less than the extent.
+    let (w, h) = (
+        extent.0.saturating_sub(1).max(1) as f64,
+        extent.1.saturating_sub(1).max(1) as f64,
+    );
+    let (room_x, room_y) = (
+        options.paper.width - 2.0 * options.margin,
+        options.paper.height - 2.0 * options.margin,
+    );
+    let scale = options
+        .scale
+        .unwrap_or_else(|| (room_x / w).min(room_y / h).max(0.0));
+    let left = options.margin + (room_x - w * scale) / 2.0;
+    let top = options.margin + (room_y - h * scale) / 2.0;
+
+    let mut pens: Vec<Pen> = Vec::new();
+    let len = options.palette.len();
+    for (i, level) in levels.iter().enumerate() {
+        let colour = &options.palette[(len - 1) - (i % len)];
+        let index = level.kind == Some(ContourKind::Index);
+        let pen = match pens
+            .iter()
+            .position(|pen| &pen.colour == colour && pen.index == index)
+        {
+            Some(pen) => pen,
+            None => {
+                pens.push(Pen {
+                    number: pens.len() + 1,
+                    colour: colour.clone(),
+                    index,
+                    strokes: Vec::new(),
+                });
+                pens.len() - 1
+            }
+        };
+
+        // Rows run down the field and y runs up the paper.
+        let strokes = level
+            .paths
+            .iter()
+            .filter(|path| path.points.len() > 1)
+            .map(|path| {
+                path.points
+                    .iter()
+                    .map(|p| {
+                        let p = p.point(options.geometry.interpolated);
+                        (
+                            left + p.x as f64 * scale,
+                            options.paper.height - top - p.y as f64 * scale,
+                        )
+                    })
+                    .collect()
+            });
+        pens[pen].strokes.extend(strokes);
+    }
+    pens.retain(|pen| !pen.strokes.is_empty());
+    for (i, pen) in pens.iter_mut().enumerate() {
+        pen.number = i + 1;
+    }
+    pens
+}
+
+/// Reorders the strokes of each pen to shorten the travel with the pen up,
+/// starting from the origin and carrying on from where the previous pen
+/// stopped, drawing open strokes backwards too with `reverse`. Returns the
+/// travel of each pen before and after, in millimetres.
+pub fn optimise_travel(pens: &mut [Pen], reverse: bool) -> Vec<(f64, f64)> {
+    let mut position = (0.0, 0.0);
+    pens.iter_mut()
+        .map(|pen| {
+            let before = travel::travel(position, &pen.strokes);
+            let start = position;
+            position = travel::optimise(start, &mut pen.strokes, reverse);
+            (before, travel::travel(start, &pen.strokes))
+        })
+        .collect()
+}
+
+fn write_hpgl<W: Write>(out: &mut W, pens: &[Pen], options: &PlotOptions) -> io::Result<()> {
+    let unit = |v: f64| (v * HPGL_UNITS).round() as i64;
+    writeln!(out, "IN;")?;
+    for pen in pens {
+        // VS takes centimetres per second.
+        writeln!(out, "SP{};VS{};", pen.number, options.feed_rate / 600.0)?;
+        for stroke in &pen.strokes {
+            let (x, y) = stroke[0];
+            writeln!(out, "PU{},{};", unit(x), unit(y))?;
+            let rest = stroke[1..]
+                .iter()
+                .map(|&(x, y)| format!("{},{}", unit(x), unit(y)));
+            writeln!(out, "PD{};", join(rest, ","))?;
+        }
+    }
+    writeln!(out, "PU;SP0;")
+}
+
+fn write_gcode<W: Write>(out: &mut W, pens: &[Pen], options: &PlotOptions) -> io::Result<()> {
+    writeln!(out, "G21 ; millimetres")?;
+    writeln!(out, "G90 ; absolute positions")?;
+    writeln!(out, "{}", options.pen_up)?;
+    for pen in pens {
+        if pens.len() > 1 {
+            let index = if pen.index { " index" } else { "" };
+            writeln!(out, "M0 ; pen {} {}{index}", pen.number, pen.colour)?;
+        }
+        for stroke in &pen.strokes {
+            let (x, y) = stroke[0];
+            writeln!(out, "G0 X{x:.3} Y{y:.3}")?;
+            writeln!(out, "{}", options.pen_down)?;
+            for (j, &(x, y)) in stroke[1..].iter().enumerate() {
+                if j == 0 {
+                    writeln!(out, "G1 X{x:.3} Y{y:.3} F{}", options.feed_rate)?;
+                } else {
+                    writeln!(out, "G1 X{x:.3} Y{y:.3}")?;
+                }
+            }
+            writeln!(out, "{}", options.pen_up)?;
+        }
+    }
+    writeln!(out, "G0 X0 Y0")?;
+    writeln!(out, "M2")
+}
+
+/// Writes the pens' strokes in the chosen format. G-code pauses with `M0`
+/// before each pen when more than one is needed, for it to be swapped.
+pub fn write_plot<W: Write>(out: &mut W, pens: &[Pen], options: &PlotOptions) -> io::Result<()> {
+    match options.format {
+        PlotFormat::Hpgl => write_hpgl(out, pens, options),
+        PlotFormat::Gcode => write_gcode(out, pens, options),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Path;
+
+    fn levels() -> Vec<ContourLevel> {
+        [1.0, 2.0, 3.0]
+            .into_iter()
+            .map(|elevation| ContourLevel {
+                elevation,
+                paths: vec![Path::from_xy(&[(0.0, 0.0), (elevation, 2.0)], false)],
+                kind: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn paper_test() {
+        assert_eq!(Ok(Paper::A4), "A4".parse());
+        assert_eq!(Ok(Paper::A3.landscape()), "a3-landscape".parse());
+        assert_eq!(
+            Ok(Paper {
+                width: 100.0,
+                height: 50.5
+            }),
+            "100x50.5".parse()
+        );
+        assert!("a9".parse::<Paper>().is_err());
+        assert!("0x10".parse::<Paper>().is_err());
+    }
+
+    #[test]
+    fn pens_test() {
+        let options = PlotOptions {
+            palette: vec!["red".to_owned(), "blue".to_owned()],
+            paper: Paper {
+                width: 100.0,
+                height: 60.0,
+            },
+            ..Default::default()
+        };
+        // A 5x3 field fills the 40mm height at 20mm per pixel, centred
+        // across the 80mm width.
+        let pens = pens((5, 3), &levels(), &options);
+        assert_eq!(
+            vec![
+                Pen {
+                    number: 1,
+                    colour: "blue".to_owned(),
+                    index: false,
+                    strokes: vec![
+                        vec![(10.0, 50.0), (30.0, 10.0)],
+                        vec![(10.0, 50.0), (70.0, 10.0)]
+                    ],
+                },
+                Pen {
+                    number: 2,
+                    colour: "red".to_owned(),
+                    index: false,
+                    strokes: vec![vec![(10.0, 50.0), (50.0, 10.0)]],
+                },
+            ],
+            pens
+        );
+
+        // The index contour at 3 gets a blue pen of its own.
+        let mut levels = levels();
+        crate::classify_levels(&mut levels, 0.0, Some(1.0), 3);
+        let classified = super::pens((5, 3), &levels, &options);
+        assert_eq!(
+            vec![("blue", false, 1), ("red", false, 1), ("blue", true, 1)],
+            classified
+                .iter()
+                .map(|pen| (pen.colour.as_str(), pen.index, pen.strokes.len()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn hpgl_test() {
+        let options = PlotOptions::default();
+        let pens = [Pen {
+            number: 2,
+            colour: "black".to_owned(),
+            index: false,
+            strokes: vec![vec![(10.0, 20.0), (11.0, 20.0), (11.0, 21.5)]],
+        }];
+
+        let mut out = Vec::new();
+        write_plot(&mut out, &pens, &options).unwrap();
+        assert_eq!(
+            "IN;\nSP2;VS2.5;\nPU400,800;\nPD440,800,440,860;\nPU;SP0;\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+
+    #[test]
+    fn gcode_test() {
+        let options = PlotOptions {
+            format: PlotFormat::Gcode,
+            pen_up: "M5".to_owned(),
+            pen_down: "M3 S90".to_owned(),
+            ..Default::default()
+        };
+        let pens = [
+            Pen {
+                number: 1,
+                colour: "red".to_owned(),
+                index: false,
+                strokes: vec![vec![(1.0, 2.0), (3.0, 4.0), (5.0, 6.0)]],
+            },
+            Pen {
+                number: 2,
+                colour: "blue".to_owned(),
+                index: false,
+                strokes: vec![vec![(0.5, 0.5), (1.0, 1.0)]],
+            },
+        ];
+
+        let mut out = Vec::new();
+        write_plot(&mut out, &pens, &options).unwrap();
+        assert_eq!(
+            "G21 ; millimetres\nG90 ; absolute positions\nM5\n\
+             M0 ; pen 1 red\n\
+             G0 X1.000 Y2.000\nM3 S90\nG1 X3.000 Y4.000 F1500\nG1 X5.000 Y6.000\nM5\n\
+             M0 ; pen 2 blue\n\
+             G0 X0.500 Y0.500\nM3 S90\nG1 X1.000 Y1.000 F1500\nM5\n\
+             G0 X0 Y0\nM2\n",
+            String::from_utf8(out).unwrap()
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{error::Result, marching_squares::Field, util::*};
+
+/// An outer ring and the holes cut out of it. Rings are closed paths that
+/// repeat their first point at the end.
+///
+/// Outer rings wind counter-clockwise and holes clockwise, taking y as
+/// pointing up, so the outer ring has a positive [`Path::signed_area`]. In
+/// the field's own y-down coordinates that looks clockwise on screen, and it
+/// flips back to counter-clockwise once the y axis is mapped upwards.
+#[derive(Debug, Clone)]
+pub struct Polygon {
+    pub exterior: Path,
+    pub holes: Vec<Path>,
+}
+
+impl Polygon {
+    /// SVG path data with every ring as its own subpath, suitable for either
+    /// fill rule.
+    pub fn to_svg(&self, interpolated: bool) -> String {
+        std::iter::once(&self.exterior)
+            .chain(&self.holes)
+            .map(|ring| ring.to_svg(interpolated))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Reverses any ring that doesn't follow the winding described above.
+    pub fn normalise_winding(&mut self, interpolated: bool) {
+        if self.exterior.signed_area(interpolated) < 0.0 {
+            self.exterior.reverse();
+        }
+        for hole in &mut self.holes {
+            if hole.signed_area(interpolated) > 0.0 {
+                hole.reverse();
+            }
+        }
+    }
+}
+
+/// Groups closed rings into polygons by how deeply they're nested, whatever
+/// their winding. Rings inside an even number of others are outer rings and
+/// the rest are holes of the ring directly around them. Winding is normalised
+/// and open paths are ignored.
+pub fn group_rings(rings: Vec<Path>, interpolated: bool) -> Vec<Polygon> {
+    let mut rings: Vec<(f32, Path)> = rings
+        .into_iter()
+        .filter(|ring| ring.closed && !ring.points.is_empty())
+        .map(|ring| (ring.signed_area(interpolated).abs(), ring))
+        .collect();
+    // Anything that contains a ring is bigger than it, so parents come first.
+    rings.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    let mut parents: Vec<Option<usize>> = Vec::with_capacity(rings.len());
+    let mut depths: Vec<usize> = Vec::with_capacity(rings.len());
+    for (i, (_, ring)) in rings.iter().enumerate() {
+        let probe = ring.start().point(interpolated);
+        let parent = (0..i)
+            .rev()
+            .find(|&j| rings[j].1.contains(probe, interpolated));
+        parents.push(parent);
+        depths.push(parent.map_or(0, |j| depths[j] + 1));
+    }
+
+    let mut owners: Vec<usize> = Vec::with_capacity(rings.len());
+    let mut polygons: Vec<Polygon> = Vec::new();
+    for (i, (_, ring)) in rings.into_iter().enumerate() {
+        if depths[i].is_multiple_of(2) {
+            owners.push(polygons.len());
+            polygons.push(Polygon {
+                exterior: ring,
+                holes: Vec::new(),
+            });
+        } else {
+            let owner = owners[parents[i].expect("Holes are inside another ring")];
+            owners.push(owner);
+            polygons[owner].holes.push(ring);
+        }
+    }
+
+    for polygon in &mut polygons {
+        polygon.normalise_winding(interpolated);
+    }
+    polygons
+}
+
+/// Groups rings into polygons using their winding, positive signed area for
+/// outer rings and negative for holes. Each hole goes to the smallest outer
+/// ring that contains it; holes that aren't inside any outer ring are dropped.
+pub(crate) fn polygons_from_rings(rings: Vec<Path>) -> Vec<Polygon> {
+    let (exteriors, holes): (Vec<_>, Vec<_>) = rings
+        .into_iter()
+        .map(|ring| (ring.signed_area(true), ring))
+        .partition(|(area, _)| *area > 0.0);
+
+    let mut polygons: Vec<(f32, Polygon)> = exteriors
+        .into_iter()
+        .map(|(area, exterior)| {
+            (
+                area,
+                Polygon {
+                    exterior,
+                    holes: Vec::new(),
+                },
+            )
+        })
+        .collect();
+
+    for (_, hole) in holes {
+        let probe = hole.start().interpolated_point;
+        let owner = polygons
+            .iter_mut()
+            .filter(|(_, polygon)| polygon.exterior.contains(probe, true))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+        if let Some((_, polygon)) = owner {
+            polygon.holes.push(hole);
+        }
+    }
+
+    polygons.into_iter().map(|(_, polygon)| polygon).collect()
+}
+
+impl Field {
+    /// Polygons covering everywhere the field is above `threshold`. Their
+    /// rings are the closed contours at that threshold, with contours that
+    /// leave the field closed along its edge.
+    pub fn level_polygons(&self, threshold: f32) -> Result<Vec<Polygon>> {
+        self.band_polygons(threshold, f32::INFINITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f32, y: f32, size: f32) -> Path {
+        let corners = [
+            (x, y),
+            (x + size, y),
+            (x + size, y + size),
+            (x, y + size),
+            (x, y),
+        ];
+        Path::from_xy(&corners, true)
+    }
+
+    #[test]
+    fn nesting_test() {
+        let mut hole = square(2.0, 2.0, 6.0);
+        hole.reverse();
+        let rings = vec![square(4.0, 4.0, 2.0), hole, square(0.0, 0.0, 10.0)];
+
+        let polygons = group_rings(rings, true);
+        assert_eq!(2, polygons.len());
+        assert_eq!(100.0, polygons[0].exterior.signed_area(true));
+        assert_eq!(1, polygons[0].holes.len());
+        assert_eq!(-36.0, polygons[0].holes[0].signed_area(true));
+        assert_eq!(4.0, polygons[1].exterior.signed_area(true));
+        assert!(polygons[1].holes.is_empty());
+    }
+
+    #[test]
+    fn level_polygons_test() {
+        // A pit in a plateau is a hole in a polygon that follows the border.
+        #[rustfmt::skip]
+        let field = Field::new((4, 4), vec![
+            9.0, 9.0, 9.0, 9.0,
+            9.0, 0.0, 9.0, 9.0,
+            9.0, 9.0, 9.0, 9.0,
+            9.0, 9.0, 9.0, 9.0,
+        ]).unwrap();
+
+        let polygons = field.level_polygons(5.0).unwrap();
+        assert_eq!(1, polygons.len());
+        assert_eq!(9.0, polygons[0].exterior.signed_area(true));
+        assert_eq!(1, polygons[0].holes.len());
+        assert!(polygons[0].holes[0].signed_area(true) < 0.0);
+
+        let traced = field.trace_paths(5.0, Mode::Strict).unwrap().0;
+        assert_eq!(
+            traced[0].signed_area(true),
+            polygons[0].holes[0].signed_area(true)
+        );
+    }
+}
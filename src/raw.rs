@@ -0,0 +1,510 @@
+//! Readers for rasters stored as bare samples: SRTM `.hgt` tiles, and BIL
+//! and FLT grids described by a `.hdr` file next to them.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufRead, BufReader},
+    path::Path as FsPath,
+};
+
+use crate::{
+    ascii::{grid_error, lines, number, parse_error, AsciiHeader},
+    error::{Error, Result},
+    geo::GeoTransform,
+    marching_squares::Field,
+};
+
+/// Value of samples without data in SRTM tiles, and in 16 bit BIL grids
+/// that don't say otherwise.
+pub const SRTM_VOID: f32 = -32768.0;
+
+/// EPSG code of WGS 84 latitude and longitude, which SRTM tiles are in.
+const WGS84: u32 = 4326;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SampleType {
+    Unsigned(usize),
+    Signed(usize),
+    Float(usize),
+}
+
+impl SampleType {
+    fn bytes(self) -> usize {
+        match self {
+            SampleType::Unsigned(bytes) | SampleType::Signed(bytes) | SampleType::Float(bytes) => {
+                bytes
+            }
+        }
+    }
+
+    fn decode(self, b: &[u8], big_endian: bool) -> f32 {
+        macro_rules! decode {
+            ($t:ty, $n:literal) => {{
+                let bytes: [u8; $n] = b.try_into().unwrap();
+                if big_endian {
+                    <$t>::from_be_bytes(bytes) as f32
+                } else {
+                    <$t>::from_le_bytes(bytes) as f32
+                }
+            }};
+        }
+        match self {
+            SampleType::Unsigned(1) => b[0] as f32,
+            SampleType::Signed(1) => b[0] as i8 as f32,
+            SampleType::Unsigned(2) => decode!(u16, 2),
+            SampleType::Signed(2) => decode!(i16, 2),
+            SampleType::Unsigned(4) => decode!(u32, 4),
+            SampleType::Signed(4) => decode!(i32, 4),
+            SampleType::Float(4) => decode!(f32, 4),
+            SampleType::Float(8) => decode!(f64, 8),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn too_large() -> Error {
+    grid_error("the header describes more bytes than can be addressed")
+}
+
+// Where the samples of the first band are in the file.
+struct Layout {
+    extent: (u32, u32),
+    sample: SampleType,
+    big_endian: bool,
+    skip: usize,
+    // Bytes from one row of the first band to the next, and from one of its
+    // samples to the next.
+    row_bytes: usize,
+    sample_bytes: usize,
+}
+
+impl Layout {
+    fn read(&self, data: &[u8]) -> Result<Vec<f32>> {
+        let (w, h) = (self.extent.0 as usize, self.extent.1 as usize);
+        let bytes = self.sample.bytes();
+        // The sizes come from the header, so they can be absurdly large.
+        let needed = [
+            (h - 1).checked_mul(self.row_bytes),
+            (w - 1).checked_mul(self.sample_bytes),
+            Some(self.skip),
+            Some(bytes),
+        ]
+        .into_iter()
+        .try_fold(0usize, |total, part| total.checked_add(part?))
+        .ok_or_else(too_large)?;
+        if data.len() < needed {
+            return Err(grid_error(format!(
+                "{} bytes are too few for a {w}x{h} grid, which needs {needed}",
+                data.len()
+            )));
+        }
+        let mut vals = Vec::with_capacity(w * h);
+        for row in 0..h {
+            for column in 0..w {
+                let at = self.skip + row * self.row_bytes + column * self.sample_bytes;
+                vals.push(self.sample.decode(&data[at..at + bytes], self.big_endian));
+            }
+        }
+        Ok(vals)
+    }
+}
+
+// The keys of a `.hdr` file in lower case, with the line and value of each.
+fn read_header(reader: impl BufRead) -> Result<HashMap<String, (usize, String)>> {
+    let mut header = HashMap::new();
+    for line in lines(reader) {
+        let (n, line) = line?;
+        let mut words = line.split_whitespace();
+        let Some(key) = words.next() else {
+            continue;
+        };
+        let Some(value) = words.next() else {
+            return Err(parse_error(n, format!("{key:?} has no value")));
+        };
+        header.insert(key.to_ascii_lowercase(), (n, value.to_owned()));
+    }
+    Ok(header)
+}
+
+// The latitude and longitude of the south west corner of an SRTM tile from
+// its name, such as N45W122.hgt.
+fn tile_origin(name: &str) -> Option<(f64, f64)> {
+    let name = name.get(..7)?.to_ascii_uppercase();
+    let lat: f64 = name.get(1..3)?.parse().ok()?;
+    let lon: f64 = name.get(4..7)?.parse().ok()?;
+    let lat = match &name[0..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => return None,
+    };
+    let lon = match &name[3..4] {
+        "E" => lon,
+        "W" => -lon,
+        _ => return None,
+    };
+    Some((lon, lat))
+}
+
+impl Field {
+    /// Loads an SRTM tile, georeferenced in WGS 84 from the position in its
+    /// name when it has the usual one like `N45W122.hgt`.
+    pub fn open_hgt(path: impl AsRef<FsPath>) -> Result<Self> {
+        let path = path.as_ref();
+        let field = Field::read_hgt(&fs::read(path)?)?;
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("");
+        let Some((lon, lat)) = tile_origin(name) else {
+            return Ok(field);
+        };
+        // Samples sit on whole degrees at the edges, so the first is the
+        // north west corner of the tile.
+        let step = 1.0 / (field.extent.0 - 1) as f64;
+        Ok(field
+            .with_transform(GeoTransform::north_up((lon, lat + 1.0), (step, step)))
+            .with_crs(WGS84))
+    }
+
+    /// Reads an SRTM tile of big-endian 16 bit samples, either 3601 or 1201
+    /// of them square for SRTM1 and SRTM3. Voids become nodata.
+    pub fn read_hgt(data: &[u8]) -> Result<Self> {
+        let side = match data.len() {
+            25_934_402 => 3601,
+            2_884_802 => 1201,
+            len => {
+                return Err(grid_error(format!(
+                    "{len} bytes is the size of neither an SRTM1 nor an SRTM3 tile"
+                )))
+            }
+        };
+        let layout = Layout {
+            extent: (side, side),
+            sample: SampleType::Signed(2),
+            big_endian: true,
+            skip: 0,
+            row_bytes: side as usize * 2,
+            sample_bytes: 2,
+        };
+        Ok(Field::new(layout.extent, layout.read(data)?)?.with_nodata(SRTM_VOID))
+    }
+
+    /// Loads a BIL, BIP or BSQ grid with the `.hdr` file of the same name.
+    pub fn open_bil(path: impl AsRef<FsPath>) -> Result<Self> {
+        let path = path.as_ref();
+        let header = BufReader::new(File::open(path.with_extension("hdr"))?);
+        Field::read_bil(&fs::read(path)?, header)
+    }
+
+    /// Reads the first band of a BIL, BIP or BSQ grid from its samples and
+    /// the text of its ESRI `.hdr` file. The georeferencing comes from
+    /// `ULXMAP`, `ULYMAP`, `XDIM` and `YDIM` when they're there, and
+    /// `NODATA` is nodata, or -32768 for signed 16 bit samples without it.
+    pub fn read_bil(data: &[u8], header: impl BufRead) -> Result<Self> {
+        let header = read_header(header)?;
+        let get = |key: &str| -> Result<Option<f64>> {
+            header
+                .get(key)
+                .map(|(n, value)| number(*n, value))
+                .transpose()
+        };
+        let word = |key: &str, default: &str| -> (usize, String) {
+            header
+                .get(key)
+                .map(|(n, value)| (*n, value.to_ascii_uppercase()))
+                .unwrap_or((0, default.to_owned()))
+        };
+        let count = |key: &str, default: usize| -> Result<usize> {
+            match header.get(key) {
+                Some((n, value)) => number(*n, value),
+                None => Ok(default),
+            }
+        };
+
+        // Parsed as they're stored in the extent, so they can't be cut short.
+        let dimension = |key: &str| -> Result<u32> {
+            match header.get(key) {
+                Some((n, value)) => number(*n, value),
+                None => Ok(0),
+            }
+        };
+        let extent = (dimension("ncols")?, dimension("nrows")?);
+        if extent.0 == 0 || extent.1 == 0 {
+            return Err(grid_error("the header needs a positive ncols and nrows"));
+        }
+        let ncols = extent.0 as usize;
+        let bands = count("nbands", 1)?.max(1);
+        let bits = count("nbits", 8)?;
+        let (n, pixel_type) = word("pixeltype", "UNSIGNEDINT");
+        let sample = match (pixel_type.as_str(), bits) {
+            ("UNSIGNEDINT", 8 | 16 | 32) => SampleType::Unsigned(bits / 8),
+            ("SIGNEDINT", 8 | 16 | 32) => SampleType::Signed(bits / 8),
+            ("FLOAT", 32 | 64) => SampleType::Float(bits / 8),
+            _ => {
+                return Err(parse_error(
+                    n,
+                    format!("{bits} bit {pixel_type} samples aren't supported"),
+                ))
+            }
+        };
+        let bytes = sample.bytes();
+        let (n, byte_order) = word("byteorder", "I");
+        let big_endian = match byte_order.as_str() {
+            "I" | "L" | "LSBFIRST" => false,
+            "M" | "B" | "MSBFIRST" => true,
+            _ => return Err(parse_error(n, format!("unknown byte order {byte_order:?}"))),
+        };
+        let (n, interleave) = word("layout", "BIL");
+        let product = |a: usize, b: usize| a.checked_mul(b).ok_or_else(too_large);
+        let band_row_bytes = count("bandrowbytes", ncols * bytes)?;
+        let (row_bytes, sample_bytes) = match interleave.as_str() {
+            "BIL" => (
+                count("totalrowbytes", product(bands, band_row_bytes)?)?,
+                bytes,
+            ),
+            "BIP" => {
+                let sample_bytes = product(bands, bytes)?;
+                (
+                    count("totalrowbytes", product(ncols, sample_bytes)?)?,
+                    sample_bytes,
+                )
+            }
+            "BSQ" => (band_row_bytes, bytes),
+            _ => return Err(parse_error(n, format!("unknown layout {interleave:?}"))),
+        };
+        // Rows closer together than their samples reach would overlap, and
+        // with no stride at all a few bytes could pass for any number of rows.
+        let row = product(ncols - 1, sample_bytes)?
+            .checked_add(bytes)
+            .ok_or_else(too_large)?;
+        if row_bytes < row {
+            return Err(grid_error(format!(
+                "rows {row_bytes} bytes apart overlap, each takes {row}"
+            )));
+        }
+        let layout = Layout {
+            extent,
+            sample,
+            big_endian,
+            skip: count("skipbytes", 0)?,
+            row_bytes,
+            sample_bytes,
+        };
+
+        let mut field = Field::new(layout.extent, layout.read(data)?)?;
+        field.nodata = match get("nodata")? {
+            Some(nodata) => Some(nodata as f32),
+            None => (sample == SampleType::Signed(2)).then_some(SRTM_VOID),
+        };
+        if let (Some(x), Some(y)) = (get("ulxmap")?, get("ulymap")?) {
+            let size = (get("xdim")?.unwrap_or(1.0), get("ydim")?.unwrap_or(1.0));
+            field = field.with_transform(GeoTransform::north_up((x, y), size));
+        }
+        Ok(field)
+    }
+
+    /// Loads an FLT grid with the `.hdr` file of the same name.
+    pub fn open_flt(path: impl AsRef<FsPath>) -> Result<Self> {
+        let path = path.as_ref();
+        let header = BufReader::new(File::open(path.with_extension("hdr"))?);
+        Field::read_flt(&fs::read(path)?, header)
+    }
+
+    /// Reads an FLT grid of 32 bit floats from its samples and its `.hdr`,
+    /// which has the keys of an ASCII Grid header and a `byteorder` of
+    /// `LSBFIRST` or `MSBFIRST`.
+    pub fn read_flt(data: &[u8], header: impl BufRead) -> Result<Self> {
+        let mut grid = AsciiHeader::default();
+        let mut big_endian = false;
+        for (key, (n, value)) in read_header(header)? {
+            if key == "byteorder" {
+                big_endian = match value.to_ascii_uppercase().as_str() {
+                    "LSBFIRST" | "I" => false,
+                    "MSBFIRST" | "M" => true,
+                    _ => return Err(parse_error(n, format!("unknown byte order {value:?}"))),
+                };
+            } else if !grid.set(n, &key, &value)? {
+                return Err(parse_error(n, format!("unknown header key {key:?}")));
+            }
+        }
+        let (extent, transform) = grid.grid()?;
+        let layout = Layout {
+            extent,
+            sample: SampleType::Float(4),
+            big_endian,
+            skip: 0,
+            row_bytes: extent.0 as usize * 4,
+            sample_bytes: 4,
+        };
+        let mut field = Field::new(extent, layout.read(data)?)?.with_transform(transform);
+        field.nodata = grid.nodata;
+        Ok(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::Point;
+
+    #[test]
+    fn hgt_test() {
+        assert_eq!(Some((-122.0, 45.0)), tile_origin("N45W122.hgt"));
+        assert_eq!(Some((7.0, -3.0)), tile_origin("s03e007.SRTMGL3.hgt"));
+        assert_eq!(None, tile_origin("tile.hgt"));
+
+        let mut data = vec![0u8; 1201 * 1201 * 2];
+        data[0..2].copy_from_slice(&(-32768i16).to_be_bytes());
+        data[2..4].copy_from_slice(&1234i16.to_be_bytes());
+        data[1201 * 2..1201 * 2 + 2].copy_from_slice(&(-12i16).to_be_bytes());
+        let field = Field::read_hgt(&data).unwrap();
+        assert_eq!((1201, 1201), field.extent);
+        assert_eq!(vec![-32768.0, 1234.0], field.vals[0..2]);
+        assert_eq!(-12.0, field.vals[1201]);
+        assert_eq!((-12.0, 1234.0), field.range());
+
+        assert_eq!(
+            "1000 bytes is the size of neither an SRTM1 nor an SRTM3 tile",
+            Field::read_hgt(&data[..1000]).unwrap_err().to_string()
+        );
+    }
+
+    #[test]
+    fn bil_test() {
+        // Two bands of big-endian signed 16 bit samples, line interleaved.
+        let header = "BYTEORDER M\n\
+                      LAYOUT BIL\n\
+                      NROWS 2\n\
+                      NCOLS 3\n\
+                      NBANDS 2\n\
+                      NBITS 16\n\
+                      PIXELTYPE SIGNEDINT\n\
+                      ULXMAP 100.5\n\
+                      ULYMAP 50.5\n\
+                      XDIM 1\n\
+                      YDIM 0.5\n";
+        let samples: [i16; 12] = [1, 2, 3, 90, 90, 90, 4, -32768, -6, 90, 90, 90];
+        let data: Vec<u8> = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+        let field = Field::read_bil(&data, header.as_bytes()).unwrap();
+        assert_eq!((3, 2), field.extent);
+        assert_eq!(vec![1.0, 2.0, 3.0, 4.0, -32768.0, -6.0], field.vals);
+        assert_eq!(Some(-32768.0), field.nodata);
+        assert_eq!((102.5, 50.0), field.to_map(Point { x: 2.0, y: 1.0 }));
+
+        // The same band on its own as little-endian floats, pixel
+        // interleaved with the second band.
+        let header =
+            "NROWS 2\nNCOLS 3\nNBANDS 2\nNBITS 32\nPIXELTYPE FLOAT\nLAYOUT BIP\nNODATA -1\n";
+        let data: Vec<u8> = [
+            1.5f32, 0.0, 2.0, 0.0, 3.0, 0.0, 4.0, 0.0, -1.0, 0.0, -6.0, 0.0,
+        ]
+        .iter()
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+        let field = Field::read_bil(&data, header.as_bytes()).unwrap();
+        assert_eq!(vec![1.5, 2.0, 3.0, 4.0, -1.0, -6.0], field.vals);
+        assert_eq!(Some(-1.0), field.nodata);
+        assert!(field.transform.is_none());
+
+        // The last sample of the second band isn't needed.
+        assert!(Field::read_bil(&data[..44], header.as_bytes()).is_ok());
+        assert_eq!(
+            "40 bytes are too few for a 3x2 grid, which needs 44",
+            Field::read_bil(&data[..40], header.as_bytes())
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "line 5: 12 bit FLOAT samples aren't supported",
+            Field::read_bil(&data, header.replace("32", "12").as_bytes())
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "the header describes more bytes than can be addressed",
+            Field::read_bil(
+                &data,
+                format!("{header}SKIPBYTES {}\n", usize::MAX).as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "the header describes more bytes than can be addressed",
+            Field::read_bil(
+                &data,
+                format!("{header}TOTALROWBYTES {}\n", usize::MAX).as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "the header describes more bytes than can be addressed",
+            Field::read_bil(
+                &data,
+                header
+                    .replace("NBANDS 2", &format!("NBANDS {}", usize::MAX))
+                    .as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "rows 0 bytes apart overlap, each takes 20",
+            Field::read_bil(
+                &data,
+                format!("{header}TOTALROWBYTES 0\nNROWS 4000000000\n").as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "rows 16 bytes apart overlap, each takes 20",
+            Field::read_bil(&data, format!("{header}TOTALROWBYTES 16\n").as_bytes())
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "rows 0 bytes apart overlap, each takes 12",
+            Field::read_bil(
+                &data,
+                header
+                    .replace("BIP", "BSQ")
+                    .replace("NROWS 2", "NROWS 4000000000\nBANDROWBYTES 0")
+                    .as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+        assert_eq!(
+            "line 2: \"4294967299\" isn't a number",
+            Field::read_bil(
+                &data,
+                header.replace("NCOLS 3", "NCOLS 4294967299").as_bytes()
+            )
+            .unwrap_err()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn flt_test() {
+        let header = "ncols 2\n\
+                      nrows 2\n\
+                      xllcorner 10\n\
+                      yllcorner 20\n\
+                      cellsize 2\n\
+                      NODATA_value -9999\n\
+                      byteorder MSBFIRST\n";
+        let data: Vec<u8> = [1.0f32, -2.5, -9999.0, 4.0]
+            .iter()
+            .flat_map(|s| s.to_be_bytes())
+            .collect();
+        let field = Field::read_flt(&data, header.as_bytes()).unwrap();
+        assert_eq!(vec![1.0, -2.5, -9999.0, 4.0], field.vals);
+        assert_eq!(Some(-9999.0), field.nodata);
+        assert_eq!((11.0, 23.0), field.to_map(Point { x: 0.0, y: 0.0 }));
+        assert!(Field::read_flt(&data[..15], header.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Elevations encoded in the colour channels of web map tiles, which a
+//! greyscale load would mangle.
+
+use std::{path::Path as FsPath, str::FromStr};
+
+use image::{io::Reader as ImageReader, ImageError, RgbaImage};
+
+use crate::{error::Result, marching_squares::Field};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RgbEncoding {
+    /// Mapzen Terrarium, `R * 256 + G + B / 256 - 32768` metres
+    Terrarium,
+    /// Mapbox Terrain-RGB, `-10000 + (R * 65536 + G * 256 + B) * 0.1` metres
+    TerrainRgb,
+}
+
+impl FromStr for RgbEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "terrarium" => Ok(RgbEncoding::Terrarium),
+            "terrain-rgb" | "terrainrgb" | "mapbox" => Ok(RgbEncoding::TerrainRgb),
+            _ => Err(format!(
+                "Unknown elevation encoding {s}, expected terrarium or terrain-rgb"
+            )),
+        }
+    }
+}
+
+impl RgbEncoding {
+    /// Elevation in metres of a pixel.
+    pub fn decode(self, [r, g, b]: [u8; 3]) -> f32 {
+        let (r, g, b) = (r as f64, g as f64, b as f64);
+        let metres = match self {
+            RgbEncoding::Terrarium => r * 256.0 + g + b / 256.0 - 32768.0,
+            RgbEncoding::TerrainRgb => -10000.0 + (r * 65536.0 + g * 256.0 + b) * 0.1,
+        };
+        metres as f32
+    }
+}
+
+impl Field {
+    /// Loads a tile with elevations encoded in its colours.
+    pub fn open_rgb(path: impl AsRef<FsPath>, encoding: RgbEncoding) -> Result<Self> {
+        let img = ImageReader::open(path)
+            .map_err(ImageError::IoError)?
+            .decode()?
+            .into_rgba8();
+        Field::from_rgb(&img, encoding)
+    }
+
+    /// Decodes the elevations of an image, leaving fully transparent pixels
+    /// without data.
+    pub fn from_rgb(img: &RgbaImage, encoding: RgbEncoding) -> Result<Self> {
+        let vals = img
+            .pixels()
+            .map(|&image::Rgba([r, g, b, a])| {
+                if a == 0 {
+                    f32::NAN
+                } else {
+                    encoding.decode([r, g, b])
+                }
+            })
+            .collect();
+        Field::new((img.width(), img.height()), vals)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_test() {
+        let terrarium = RgbEncoding::Terrarium;
+        assert_eq!(0.0, terrarium.decode([128, 0, 0]));
+        assert_eq!(-32768.0, terrarium.decode([0, 0, 0]));
+        assert_eq!(258.5, terrarium.decode([129, 2, 128]));
+        assert_eq!(-0.25, terrarium.decode([127, 255, 192]));
+
+        let terrain_rgb = RgbEncoding::TerrainRgb;
+        assert_eq!(0.0, terrain_rgb.decode([1, 134, 160]));
+        assert_eq!(-10000.0, terrain_rgb.decode([0, 0, 0]));
+        assert!((terrain_rgb.decode([1, 135, 0]) - 9.6).abs() < 1e-4);
+
+        assert_eq!(Ok(RgbEncoding::TerrainRgb), "Terrain-RGB".parse());
+        assert!("srtm".parse::<RgbEncoding>().is_err());
+    }
+
+    #[test]
+    fn image_test() {
+        let img = RgbaImage::from_raw(
+            2,
+            1,
+            vec![
+                128, 100, 0, 255, //
+                0, 0, 0, 0,
+            ],
+        )
+        .unwrap();
+        let field = Field::from_rgb(&img, RgbEncoding::Terrarium).unwrap();
+        assert_eq!((2, 1), field.extent);
+        assert_eq!(100.0, field.vals[0]);
+        assert!(field.vals[1].is_nan());
+    }
+}
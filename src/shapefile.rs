@@ -0,0 +1,369 @@
+//! ESRI Shapefile output, writing the geometry, index and attribute files of
+//! a shapefile with an `ELEV` field for every shape.
+
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{band::ContourBand, geo::Geometry, marching_squares::ContourLevel, util::Path};
+
+const FILE_CODE: i32 = 9994;
+const VERSION: i32 = 1000;
+const HEADER_LEN: usize = 100;
+
+// Width and decimal places of the ELEV field.
+const ELEV_WIDTH: usize = 12;
+const ELEV_DECIMALS: usize = 3;
+
+/// The kind of shape stored in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeType {
+    Polyline,
+    Polygon,
+}
+
+impl ShapeType {
+    fn code(self) -> i32 {
+        match self {
+            ShapeType::Polyline => 3,
+            ShapeType::Polygon => 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ShapefileOptions {
+    pub geometry: Geometry,
+    /// Last update date recorded in the attribute file, as year, month and day
+    pub date: (u16, u8, u8),
+}
+
+impl Default for ShapefileOptions {
+    fn default() -> Self {
+        Self {
+            geometry: Geometry::default(),
+            date: today(),
+        }
+    }
+}
+
+// The current UTC date.
+fn today() -> (u16, u8, u8) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    date_from_days((secs / 86_400) as i64)
+}
+
+// Converts days since the Unix epoch to a date in the proleptic Gregorian
+// calendar, counting years from March so leap days come last.
+fn date_from_days(days: i64) -> (u16, u8, u8) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year as u16, month as u8, day as u8)
+}
+
+// One record, as its parts in map coordinates.
+struct Shape {
+    parts: Vec<Vec<(f64, f64)>>,
+    elevation: f32,
+}
+
+impl Shape {
+    fn points(&self) -> impl Iterator<Item = &(f64, f64)> {
+        self.parts.iter().flatten()
+    }
+
+    fn content_len(&self) -> usize {
+        44 + 4 * self.parts.len() + 16 * self.points().count()
+    }
+}
+
+impl ShapefileOptions {
+    fn coordinates(&self, path: &Path) -> Vec<(f64, f64)> {
+        self.geometry.coordinates(path)
+    }
+
+    // Shapefile rings go clockwise around the outside of a polygon and
+    // counter-clockwise around holes.
+    fn ring(&self, path: &Path, hole: bool) -> Vec<(f64, f64)> {
+        self.geometry.ring(path, !hole)
+    }
+}
+
+fn bounds<'a>(points: impl Iterator<Item = &'a (f64, f64)>) -> [f64; 4] {
+    let mut points = points.peekable();
+    let Some(&&(x, y)) = points.peek() else {
+        return [0.0; 4];
+    };
+    points.fold([x, y, x, y], |[x0, y0, x1, y1], &(x, y)| {
+        [x0.min(x), y0.min(y), x1.max(x), y1.max(y)]
+    })
+}
+
+fn write_header<W: Write>(
+    out: &mut W,
+    shape_type: ShapeType,
+    file_len: usize,
+    bounds: [f64; 4],
+) -> io::Result<()> {
+    out.write_all(&FILE_CODE.to_be_bytes())?;
+    out.write_all(&[0; 20])?;
+    out.write_all(&((file_len / 2) as i32).to_be_bytes())?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    out.write_all(&shape_type.code().to_le_bytes())?;
+    for bound in bounds {
+        out.write_all(&bound.to_le_bytes())?;
+    }
+    // No z or m ranges.
+    out.write_all(&[0; 32])
+}
+
+fn write_dbf<W: Write>(
+    out: &mut W,
+    shapes: &[Shape],
+    options: &ShapefileOptions,
+) -> io::Result<()> {
+    let header_len = 32 + 32 + 1;
+    let record_len = 1 + ELEV_WIDTH;
+    let (year, month, day) = options.date;
+
+    out.write_all(&[0x03, (year.saturating_sub(1900)) as u8, month, day])?;
+    out.write_all(&(shapes.len() as u32).to_le_bytes())?;
+    out.write_all(&(header_len as u16).to_le_bytes())?;
+    out.write_all(&(record_len as u16).to_le_bytes())?;
+    out.write_all(&[0; 20])?;
+
+    let mut field = [0; 32];
+    field[..4].copy_from_slice(b"ELEV");
+    field[11] = b'N';
+    field[16] = ELEV_WIDTH as u8;
+    field[17] = ELEV_DECIMALS as u8;
+    out.write_all(&field)?;
+    out.write_all(&[0x0d])?;
+
+    for shape in shapes {
+        // dBase has no infinity, a blank number is a null.
+        if shape.elevation.is_finite() {
+            write!(
+                out,
+                " {:>width$.decimals$}",
+                shape.elevation,
+                width = ELEV_WIDTH,
+                decimals = ELEV_DECIMALS
+            )?;
+        } else {
+            write!(out, " {:width$}", "", width = ELEV_WIDTH)?;
+        }
+    }
+    out.write_all(&[0x1a])
+}
+
+fn write_shapes<W: Write>(
+    shp: &mut W,
+    shx: &mut W,
+    dbf: &mut W,
+    shape_type: ShapeType,
+    shapes: &[Shape],
+    options: &ShapefileOptions,
+) -> io::Result<()> {
+    let bounds = bounds(shapes.iter().flat_map(Shape::points));
+    let shp_len = HEADER_LEN + shapes.iter().map(|s| 8 + s.content_len()).sum::<usize>();
+    write_header(shp, shape_type, shp_len, bounds)?;
+    write_header(shx, shape_type, HEADER_LEN + 8 * shapes.len(), bounds)?;
+
+    let mut offset = HEADER_LEN;
+    for (i, shape) in shapes.iter().enumerate() {
+        let content_len = shape.content_len();
+        shx.write_all(&((offset / 2) as i32).to_be_bytes())?;
+        shx.write_all(&((content_len / 2) as i32).to_be_bytes())?;
+        offset += 8 + content_len;
+
+        shp.write_all(&(i as i32 + 1).to_be_bytes())?;
+        shp.write_all(&((content_len / 2) as i32).to_be_bytes())?;
+        shp.write_all(&shape_type.code().to_le_bytes())?;
+        for bound in self::bounds(shape.points()) {
+            shp.write_all(&bound.to_le_bytes())?;
+        }
+        shp.write_all(&(shape.parts.len() as i32).to_le_bytes())?;
+        shp.write_all(&(shape.points().count() as i32).to_le_bytes())?;
+        let mut start = 0;
+        for part in &shape.parts {
+            shp.write_all(&(start as i32).to_le_bytes())?;
+            start += part.len();
+        }
+        for &(x, y) in shape.points() {
+            shp.write_all(&x.to_le_bytes())?;
+            shp.write_all(&y.to_le_bytes())?;
+        }
+    }
+
+    write_dbf(dbf, shapes, options)
+}
+
+/// Writes every path with at least two points as a single part polyline,
+/// with the elevation of its level.
+pub fn write_polylines<W: Write>(
+    shp: &mut W,
+    shx: &mut W,
+    dbf: &mut W,
+    levels: &[ContourLevel],
+    options: &ShapefileOptions,
+) -> io::Result<()> {
+    let shapes: Vec<Shape> = levels
+        .iter()
+        .flat_map(|level| {
+            level
+                .paths
+                .iter()
+                .filter(|path| path.points.len() > 1)
+                .map(|path| Shape {
+                    parts: vec![options.coordinates(path)],
+                    elevation: level.elevation,
+                })
+        })
+        .collect();
+    write_shapes(shp, shx, dbf, ShapeType::Polyline, &shapes, options)
+}
+
+/// Writes every polygon of every band with its holes as extra parts, with
+/// the band's lower limit as its elevation, left blank for the lowest band.
+pub fn write_polygons<W: Write>(
+    shp: &mut W,
+    shx: &mut W,
+    dbf: &mut W,
+    bands: &[ContourBand],
+    options: &ShapefileOptions,
+) -> io::Result<()> {
+    let shapes: Vec<Shape> = bands
+        .iter()
+        .flat_map(|band| {
+            band.polygons.iter().map(|polygon| Shape {
+                parts: std::iter::once(options.ring(&polygon.exterior, false))
+                    .chain(polygon.holes.iter().map(|hole| options.ring(hole, true)))
+                    .collect(),
+                elevation: band.lower,
+            })
+        })
+        .collect();
+    write_shapes(shp, shx, dbf, ShapeType::Polygon, &shapes, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geo::GeoTransform, polygon::Polygon, util::Path};
+
+    fn options() -> ShapefileOptions {
+        ShapefileOptions {
+            geometry: Geometry {
+                interpolated: true,
+                transform: Some(GeoTransform::north_up((100.0, 200.0), (2.0, 2.0))),
+            },
+            date: (2024, 3, 9),
+        }
+    }
+
+    fn doubles(vals: &[f64]) -> Vec<u8> {
+        vals.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    // The 100 byte header shared by the .shp and .shx files.
+    fn header(shape_type: u8, words: u8, bounds: &[f64; 4]) -> Vec<u8> {
+        let mut bytes = vec![0, 0, 0x27, 0x0a];
+        bytes.extend([0; 20]);
+        bytes.extend([0, 0, 0, words]);
+        bytes.extend([0xe8, 0x03, 0, 0, shape_type, 0, 0, 0]);
+        bytes.extend(doubles(bounds));
+        bytes.extend([0; 32]);
+        bytes
+    }
+
+    #[test]
+    fn date_test() {
+        assert_eq!((1970, 1, 1), date_from_days(0));
+        assert_eq!((2000, 2, 29), date_from_days(11_016));
+        assert_eq!((2024, 3, 9), date_from_days(19_791));
+    }
+
+    #[test]
+    fn polyline_test() {
+        let levels = [ContourLevel {
+            elevation: 12.5,
+            paths: vec![Path::from_xy(&[(0.0, 0.0), (1.0, 2.0)], false)],
+            kind: None,
+        }];
+        let (mut shp, mut shx, mut dbf) = (Vec::new(), Vec::new(), Vec::new());
+        write_polylines(&mut shp, &mut shx, &mut dbf, &levels, &options()).unwrap();
+
+        // The points are (100, 200) and (102, 196).
+        let bounds = [100.0, 196.0, 102.0, 200.0];
+        let mut expected = header(3, 50 + 4 + 40, &bounds);
+        expected.extend([0, 0, 0, 1, 0, 0, 0, 40, 3, 0, 0, 0]);
+        expected.extend(doubles(&bounds));
+        expected.extend([1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend(doubles(&[100.0, 200.0, 102.0, 196.0]));
+        assert_eq!(expected, shp);
+
+        let mut expected = header(3, 50 + 4, &bounds);
+        expected.extend([0, 0, 0, 50, 0, 0, 0, 40]);
+        assert_eq!(expected, shx);
+
+        let mut expected = vec![3, 124, 3, 9, 1, 0, 0, 0, 65, 0, 13, 0];
+        expected.extend([0; 20]);
+        expected.extend(b"ELEV\0\0\0\0\0\0\0N\0\0\0\0");
+        expected.extend([12, 3]);
+        expected.extend([0; 14]);
+        expected.push(0x0d);
+        expected.extend(b"       12.500");
+        expected.push(0x1a);
+        assert_eq!(expected, dbf);
+    }
+
+    #[test]
+    fn polygon_test() {
+        // In pixel coordinates the outer ring is counter-clockwise, so it's
+        // reversed, while the hole already is.
+        let ring = [(0.0, 0.0), (4.0, 0.0), (0.0, 4.0), (0.0, 0.0)];
+        let hole = [(1.0, 1.0), (2.0, 1.0), (1.0, 2.0), (1.0, 1.0)];
+        let bands = [ContourBand {
+            lower: 5.0,
+            upper: f32::INFINITY,
+            polygons: vec![Polygon {
+                exterior: Path::from_xy(&ring, true),
+                holes: vec![Path::from_xy(&hole, true)],
+            }],
+        }];
+        let options = ShapefileOptions {
+            geometry: Geometry::default(),
+            ..options()
+        };
+        let (mut shp, mut shx, mut dbf) = (Vec::new(), Vec::new(), Vec::new());
+        write_polygons(&mut shp, &mut shx, &mut dbf, &bands, &options).unwrap();
+
+        let bounds = [0.0, 0.0, 4.0, 4.0];
+        let content_words = (44 + 2 * 4 + 8 * 16) / 2;
+        let mut expected = header(5, 50 + 4 + content_words, &bounds);
+        expected.extend([0, 0, 0, 1, 0, 0, 0, content_words, 5, 0, 0, 0]);
+        expected.extend(doubles(&bounds));
+        expected.extend([2, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0]);
+        expected.extend(doubles(&[0.0, 0.0, 0.0, 4.0, 4.0, 0.0, 0.0, 0.0]));
+        expected.extend(doubles(&[1.0, 1.0, 2.0, 1.0, 1.0, 2.0, 1.0, 1.0]));
+        assert_eq!(expected, shp);
+        assert_eq!(108, shx.len());
+        assert_eq!(b"        5.000\x1a", &dbf[dbf.len() - 14..]);
+    }
+}
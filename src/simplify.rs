@@ -0,0 +1,527 @@
+//! Simplification of contour paths that keeps their topology, so a path
+//! never crosses itself or another path and never jumps over one.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    str::FromStr,
+};
+
+use crate::{
+    geo::GeoTransform,
+    marching_squares::ContourLevel,
+    util::{Path, Point},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Keeps the point furthest from the chord of a run until every dropped
+    /// point is within the tolerance of it
+    DouglasPeucker,
+    /// Drops the point making the smallest triangle with its neighbours until
+    /// every triangle is at least the square of the tolerance
+    VisvalingamWhyatt,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dp" | "douglas-peucker" => Ok(Method::DouglasPeucker),
+            "vw" | "visvalingam-whyatt" => Ok(Method::VisvalingamWhyatt),
+            _ => Err(format!(
+                "Unknown simplification method {s}, expected dp or vw"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+    Pixels(f32),
+    /// Distance in the units of the transform, which is needed to use it
+    MapUnits(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct SimplifyOptions {
+    pub method: Method,
+    pub tolerance: Tolerance,
+    /// Simplify the interpolated geometry rather than the raw cell midpoints
+    pub interpolated: bool,
+    /// Transform to take a tolerance in map units through
+    pub transform: Option<GeoTransform>,
+}
+
+impl SimplifyOptions {
+    fn pixels(&self) -> f64 {
+        match self.tolerance {
+            Tolerance::Pixels(tolerance) => tolerance as f64,
+            Tolerance::MapUnits(tolerance) => {
+                tolerance / self.transform.map_or(1.0, |t| t.pixel_size())
+            }
+        }
+    }
+}
+
+// A segment from the point at `.1` of path `.0` to the next point kept.
+type SegmentId = (usize, usize);
+
+fn cross(o: Point, a: Point, b: Point) -> f64 {
+    (a.x as f64 - o.x as f64) * (b.y as f64 - o.y as f64)
+        - (a.y as f64 - o.y as f64) * (b.x as f64 - o.x as f64)
+}
+
+// Whether the segments cross at a point inside both of them, so touching
+// at an end or running along each other doesn't count.
+fn crosses(a: Point, b: Point, c: Point, d: Point) -> bool {
+    let (abc, abd) = (cross(a, b, c), cross(a, b, d));
+    let (cda, cdb) = (cross(c, d, a), cross(c, d, b));
+    abc * abd < 0.0 && cda * cdb < 0.0
+}
+
+// Even-odd test against the ring closed back from the last point to the first.
+fn inside(ring: &[Point], point: Point) -> bool {
+    let mut inside = false;
+    let last = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[if i == 0 { last } else { i - 1 }]);
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+fn distance_to_segment(p: Point, a: Point, b: Point) -> f64 {
+    let (dx, dy) = (b.x as f64 - a.x as f64, b.y as f64 - a.y as f64);
+    let (px, py) = (p.x as f64 - a.x as f64, p.y as f64 - a.y as f64);
+    let length = dx * dx + dy * dy;
+    let t = if length > 0.0 {
+        ((px * dx + py * dy) / length).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    (px - t * dx).hypot(py - t * dy)
+}
+
+// Every segment of every path, bucketed by the grid cells its bounding box
+// covers.
+struct SegmentGrid {
+    size: f32,
+    cells: HashMap<(i32, i32), Vec<SegmentId>>,
+    segments: HashMap<SegmentId, (Point, Point)>,
+}
+
+impl SegmentGrid {
+    fn new(size: f32) -> Self {
+        SegmentGrid {
+            size,
+            cells: HashMap::new(),
+            segments: HashMap::new(),
+        }
+    }
+
+    fn cells(&self, points: &[Point]) -> impl Iterator<Item = (i32, i32)> {
+        let (mut min, mut max) = (points[0], points[0]);
+        for p in points {
+            min = Point {
+                x: min.x.min(p.x),
+                y: min.y.min(p.y),
+            };
+            max = Point {
+                x: max.x.max(p.x),
+                y: max.y.max(p.y),
+            };
+        }
+        let cell = |v: f32| (v / self.size).floor() as i32;
+        let (x0, x1, y0, y1) = (cell(min.x), cell(max.x), cell(min.y), cell(max.y));
+        (y0..=y1).flat_map(move |y| (x0..=x1).map(move |x| (x, y)))
+    }
+
+    fn insert(&mut self, id: SegmentId, a: Point, b: Point) {
+        for cell in self.cells(&[a, b]).collect::<Vec<_>>() {
+            self.cells.entry(cell).or_default().push(id);
+        }
+        self.segments.insert(id, (a, b));
+    }
+
+    fn remove(&mut self, id: SegmentId) {
+        if let Some((a, b)) = self.segments.remove(&id) {
+            for cell in self.cells(&[a, b]).collect::<Vec<_>>() {
+                if let Some(ids) = self.cells.get_mut(&cell) {
+                    ids.retain(|&other| other != id);
+                }
+            }
+        }
+    }
+
+    // Whether the run of points can be replaced by a straight line from its
+    // first to its last without crossing any segment but the ignored ones,
+    // or leaving another segment on the other side of it.
+    fn can_shortcut(&self, run: &[Point], ignore: impl Fn(SegmentId) -> bool) -> bool {
+        let (a, b) = (run[0], run[run.len() - 1]);
+        let mut seen = HashSet::new();
+        for cell in self.cells(run) {
+            for &id in self.cells.get(&cell).into_iter().flatten() {
+                if ignore(id) || !seen.insert(id) {
+                    continue;
+                }
+                let (c, d) = self.segments[&id];
+                if crosses(a, b, c, d) || (c != a && c != b && inside(run, c)) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn douglas_peucker(
+    grid: &mut SegmentGrid,
+    path: usize,
+    points: &[Point],
+    tolerance: f64,
+) -> Vec<usize> {
+    let last = points.len() - 1;
+    let mut kept = vec![0, last];
+    // A closed path has no chord to start from, so it starts from a triangle
+    // of its start, the point furthest from it and the point furthest from
+    // the line between those.
+    if points[0] == points[last] && last > 2 {
+        let furthest_from = |a: Point, b: Point| {
+            (1..last)
+                .max_by(|&i, &j| {
+                    distance_to_segment(points[i], a, b)
+                        .total_cmp(&distance_to_segment(points[j], a, b))
+                })
+                .expect("Ring has inner points")
+        };
+        let opposite = furthest_from(points[0], points[0]);
+        let third = furthest_from(points[0], points[opposite]);
+        kept.extend([opposite, third]);
+        kept.sort_unstable();
+        kept.dedup();
+    }
+    let mut runs: Vec<(usize, usize)> = kept.windows(2).rev().map(|w| (w[0], w[1])).collect();
+
+    // Runs are taken from the front of the path first.
+    while let Some((a, b)) = runs.pop() {
+        if b - a < 2 {
+            continue;
+        }
+        let (furthest, distance) = (a + 1..b)
+            .map(|i| (i, distance_to_segment(points[i], points[a], points[b])))
+            .max_by(|x, y| x.1.total_cmp(&y.1))
+            .expect("Run has inner points");
+        if distance <= tolerance
+            && grid.can_shortcut(&points[a..=b], |(p, i)| p == path && (a..b).contains(&i))
+        {
+            for i in a..b {
+                grid.remove((path, i));
+            }
+            grid.insert((path, a), points[a], points[b]);
+        } else {
+            kept.push(furthest);
+            runs.push((furthest, b));
+            runs.push((a, furthest));
+        }
+    }
+    kept.sort_unstable();
+    kept
+}
+
+fn visvalingam_whyatt(
+    grid: &mut SegmentGrid,
+    path: usize,
+    points: &[Point],
+    tolerance: f64,
+) -> Vec<usize> {
+    let last = points.len() - 1;
+    // A closed path keeps at least a triangle.
+    let min_len = if points[0] == points[last] { 4 } else { 2 };
+    let mut prev: Vec<usize> = (0..points.len()).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (0..points.len()).map(|i| (i + 1).min(last)).collect();
+    let mut removed = vec![false; points.len()];
+    let area = |p: usize, i: usize, q: usize| (cross(points[p], points[i], points[q]) / 2.0).abs();
+
+    // Areas are never negative, so their bits sort like they do.
+    let mut areas: Vec<f32> = (0..points.len())
+        .map(|i| {
+            if i == 0 || i == last {
+                f32::INFINITY
+            } else {
+                area(i - 1, i, i + 1) as f32
+            }
+        })
+        .collect();
+    let mut heap: BinaryHeap<_> = (1..last)
+        .map(|i| Reverse((areas[i].to_bits(), i)))
+        .collect();
+
+    let threshold = (tolerance * tolerance) as f32;
+    let mut len = points.len();
+    while let Some(Reverse((bits, i))) = heap.pop() {
+        if removed[i] || bits != areas[i].to_bits() {
+            continue;
+        }
+        if areas[i] >= threshold || len <= min_len {
+            break;
+        }
+        let (p, q) = (prev[i], next[i]);
+        if !grid.can_shortcut(&[points[p], points[i], points[q]], |id| {
+            id == (path, p) || id == (path, i)
+        }) {
+            continue;
+        }
+
+        grid.remove((path, p));
+        grid.remove((path, i));
+        grid.insert((path, p), points[p], points[q]);
+        removed[i] = true;
+        len -= 1;
+        next[p] = q;
+        prev[q] = p;
+        for j in [p, q] {
+            if j != 0 && j != last {
+                areas[j] = area(prev[j], j, next[j]) as f32;
+                heap.push(Reverse((areas[j].to_bits(), j)));
+            }
+        }
+    }
+    (0..points.len()).filter(|&i| !removed[i]).collect()
+}
+
+/// Drops points that repeat the one before them.
+pub fn dedup(path: &mut Path, interpolated: bool) {
+    path.points
+        .dedup_by(|b, a| a.point(interpolated) == b.point(interpolated));
+}
+
+/// Removes repeated points and simplifies the paths together, so that none
+/// of them comes to cross itself or any other.
+pub fn simplify_paths<'a>(
+    paths: impl IntoIterator<Item = &'a mut Path>,
+    options: &SimplifyOptions,
+) {
+    let mut paths: Vec<&mut Path> = paths.into_iter().collect();
+    let tolerance = options.pixels();
+    let mut grid = SegmentGrid::new((2.0 * tolerance as f32).max(2.0));
+    for (i, path) in paths.iter_mut().enumerate() {
+        dedup(path, options.interpolated);
+        for (j, w) in path.points.windows(2).enumerate() {
+            grid.insert(
+                (i, j),
+                w[0].point(options.interpolated),
+                w[1].point(options.interpolated),
+            );
+        }
+    }
+
+    for (i, path) in paths.into_iter().enumerate() {
+        if path.points.len() < 3 {
+            continue;
+        }
+        let points: Vec<Point> = path
+            .points
+            .iter()
+            .map(|p| p.point(options.interpolated))
+            .collect();
+        let kept = match options.method {
+            Method::DouglasPeucker => douglas_peucker(&mut grid, i, &points, tolerance),
+            Method::VisvalingamWhyatt => visvalingam_whyatt(&mut grid, i, &points, tolerance),
+        };
+        let mut kept = kept.into_iter().peekable();
+        let mut index = 0;
+        path.points.retain(|_| {
+            let keep = kept.next_if_eq(&index).is_some();
+            index += 1;
+            keep
+        });
+    }
+}
+
+/// Simplifies the paths of every level together, so that contours of
+/// neighbouring levels never cross.
+pub fn simplify_levels(levels: &mut [ContourLevel], options: &SimplifyOptions) {
+    simplify_paths(
+        levels.iter_mut().flat_map(|level| &mut level.paths),
+        options,
+    );
+}
+
+impl Path {
+    /// A simplified copy of the path on its own, which doesn't cross itself.
+    pub fn simplify(&self, options: &SimplifyOptions) -> Path {
+        let mut path = self.clone();
+        simplify_paths([&mut path], options);
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Field;
+
+    fn coordinates(path: &Path) -> Vec<(f32, f32)> {
+        path.points
+            .iter()
+            .map(|p| (p.interpolated_point.x, p.interpolated_point.y))
+            .collect()
+    }
+
+    fn options(method: Method, tolerance: f32) -> SimplifyOptions {
+        SimplifyOptions {
+            method,
+            tolerance: Tolerance::Pixels(tolerance),
+            interpolated: true,
+            transform: None,
+        }
+    }
+
+    #[test]
+    fn douglas_peucker_test() {
+        let line = Path::from_xy(
+            &[
+                (0.0, 0.0),
+                (1.0, 0.1),
+                (1.0, 0.1),
+                (2.0, -0.1),
+                (3.0, 5.0),
+                (4.0, 6.0),
+                (5.0, 7.0),
+            ],
+            false,
+        );
+        let simplified = line.simplify(&options(Method::DouglasPeucker, 0.5));
+        assert_eq!(
+            vec![(0.0, 0.0), (2.0, -0.1), (3.0, 5.0), (5.0, 7.0)],
+            coordinates(&simplified)
+        );
+
+        // Only the repeated and the collinear points go without a tolerance.
+        let simplified = line.simplify(&options(Method::DouglasPeucker, 0.0));
+        assert_eq!(5, simplified.points.len());
+    }
+
+    #[test]
+    fn visvalingam_whyatt_test() {
+        let line = Path::from_xy(
+            &[(0.0, 0.0), (1.0, 0.1), (2.0, 0.0), (3.0, 3.0), (4.0, 0.0)],
+            false,
+        );
+        let simplified = line.simplify(&options(Method::VisvalingamWhyatt, 1.0));
+        assert_eq!(
+            vec![(0.0, 0.0), (2.0, 0.0), (3.0, 3.0), (4.0, 0.0)],
+            coordinates(&simplified)
+        );
+    }
+
+    #[test]
+    fn closed_test() {
+        let ring = Path::from_xy(
+            &[
+                (0.0, 0.0),
+                (1.0, 0.0),
+                (2.0, 0.0),
+                (2.0, 1.0),
+                (2.0, 2.0),
+                (1.0, 2.0),
+                (0.0, 2.0),
+                (0.0, 1.0),
+                (0.0, 0.0),
+            ],
+            true,
+        );
+        for method in [Method::DouglasPeucker, Method::VisvalingamWhyatt] {
+            let simplified = ring.simplify(&options(method, 0.5));
+            assert_eq!(
+                vec![(0.0, 0.0), (2.0, 0.0), (2.0, 2.0), (0.0, 2.0), (0.0, 0.0)],
+                coordinates(&simplified)
+            );
+
+            // Far past the size of the ring it still keeps a triangle.
+            let simplified = ring.simplify(&options(method, 10.0));
+            assert_eq!(4, simplified.points.len());
+        }
+    }
+
+    #[test]
+    fn topology_test() {
+        // A bend that would be cut straight if it weren't wrapped around a
+        // small ring of the next level up.
+        let mut levels = [
+            ContourLevel {
+                elevation: 1.0,
+                paths: vec![Path::from_xy(&[(0.0, 0.0), (2.0, 1.0), (4.0, 0.0)], false)],
+                kind: None,
+            },
+            ContourLevel {
+                elevation: 2.0,
+                paths: vec![Path::from_xy(
+                    &[(2.0, 0.4), (2.2, 0.6), (2.0, 0.8), (1.8, 0.6), (2.0, 0.4)],
+                    true,
+                )],
+                kind: None,
+            },
+        ];
+        for method in [Method::DouglasPeucker, Method::VisvalingamWhyatt] {
+            let mut levels = levels.clone();
+            simplify_levels(&mut levels, &options(method, 2.0));
+            assert_eq!(3, levels[0].paths[0].points.len());
+        }
+
+        // With the ring out of the way the bend goes.
+        levels[1].paths.clear();
+        simplify_levels(&mut levels, &options(Method::DouglasPeucker, 2.0));
+        assert_eq!(2, levels[0].paths[0].points.len());
+    }
+
+    #[test]
+    fn field_test() {
+        // Simplified contours of neighbouring levels never cross.
+        let (w, h) = (60, 50);
+        let vals: Vec<f32> = (0..w * h)
+            .map(|i| {
+                let (x, y) = ((i % w) as f32 / 5.0, (i / w) as f32 / 7.0);
+                x.sin() * y.cos() + (x * 0.3 + y * 0.7).sin()
+            })
+            .collect();
+        let field = Field::new((w as u32, h as u32), vals).unwrap();
+        let thresholds: Vec<f32> = (-8..8).map(|i| i as f32 * 0.2).collect();
+        for method in [Method::DouglasPeucker, Method::VisvalingamWhyatt] {
+            let mut levels = field
+                .contours(&thresholds, true, crate::Mode::Strict)
+                .unwrap()
+                .0;
+            let before: usize = levels
+                .iter()
+                .flat_map(|l| &l.paths)
+                .map(|p| p.points.len())
+                .sum();
+            simplify_levels(&mut levels, &options(method, 1.5));
+            let after: usize = levels
+                .iter()
+                .flat_map(|l| &l.paths)
+                .map(|p| p.points.len())
+                .sum();
+            assert!(after * 2 < before);
+
+            let segments: Vec<(Point, Point)> = levels
+                .iter()
+                .flat_map(|l| &l.paths)
+                .flat_map(|p| p.points.windows(2))
+                .map(|w| (w[0].interpolated_point, w[1].interpolated_point))
+                .collect();
+            for (i, &(a, b)) in segments.iter().enumerate() {
+                for &(c, d) in &segments[i + 1..] {
+                    assert!(!crosses(a, b, c, d), "{a:?} {b:?} crosses {c:?} {d:?}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! Smoothing of contour paths, by corner cutting or by fitting a spline
+//! through their points, and the cubic Bézier segments SVG curves are drawn
+//! with. Points on the border of the field stay where they are.
+
+use std::str::FromStr;
+
+use crate::{
+    marching_squares::ContourLevel,
+    util::{Path, Point, RichPoint},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    /// Chaikin corner cutting, which replaces every corner by two points a
+    /// quarter of the way along its sides at each step
+    Chaikin,
+    /// Centripetal Catmull-Rom spline through the points, sampled with
+    /// `steps` segments between each pair of them
+    CatmullRom,
+}
+
+impl FromStr for Method {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "chaikin" => Ok(Method::Chaikin),
+            "catmull-rom" | "catmullrom" => Ok(Method::CatmullRom),
+            _ => Err(format!(
+                "Unknown smoothing method {s}, expected chaikin or catmull-rom"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SmoothOptions {
+    pub method: Method,
+    /// Rounds of corner cutting, or spline segments between every two points
+    pub steps: usize,
+    /// Extent of the field the paths were traced in, to find its border
+    pub extent: (u32, u32),
+}
+
+pub(crate) fn on_border(point: Point, extent: (u32, u32)) -> bool {
+    let (w, h) = (extent.0 as f32 - 1.0, extent.1 as f32 - 1.0);
+    point.x <= 0.0 || point.y <= 0.0 || point.x >= w || point.y >= h
+}
+
+fn pinned(point: &RichPoint, extent: (u32, u32)) -> bool {
+    on_border(point.raw_point, extent) || on_border(point.interpolated_point, extent)
+}
+
+fn lerp(a: Point, b: Point, t: f32) -> Point {
+    Point {
+        x: a.x + (b.x - a.x) * t,
+        y: a.y + (b.y - a.y) * t,
+    }
+}
+
+fn mix(a: &RichPoint, b: &RichPoint, t: f32) -> RichPoint {
+    RichPoint {
+        raw_point: lerp(a.raw_point, b.raw_point, t),
+        interpolated_point: lerp(a.interpolated_point, b.interpolated_point, t),
+    }
+}
+
+// One round of corner cutting. An open run keeps its ends, a cyclic one is
+// given without repeating its first point.
+fn chaikin(points: &[RichPoint], cyclic: bool) -> Vec<RichPoint> {
+    let n = points.len();
+    let segments = if cyclic { n } else { n - 1 };
+    let mut out = Vec::with_capacity(2 * n);
+    if !cyclic {
+        out.push(points[0].clone());
+    }
+    for i in 0..segments {
+        let (a, b) = (&points[i], &points[(i + 1) % n]);
+        if cyclic || i > 0 {
+            out.push(mix(a, b, 0.25));
+        }
+        if cyclic || i + 1 < segments {
+            out.push(mix(a, b, 0.75));
+        }
+    }
+    if !cyclic {
+        out.push(points[n - 1].clone());
+    }
+    out
+}
+
+// Centripetal Catmull-Rom through the points, evaluated the Barry-Goldman
+// way. Open runs are extended by reflecting their second and second last
+// points through their ends.
+fn catmull_rom(points: &[Point], cyclic: bool, steps: usize) -> Vec<Point> {
+    let n = points.len();
+    let at = |i: isize| -> Point {
+        if cyclic {
+            points[i.rem_euclid(n as isize) as usize]
+        } else if i < 0 {
+            lerp(points[1], points[0], 2.0)
+        } else if i as usize >= n {
+            lerp(points[n - 2], points[n - 1], 2.0)
+        } else {
+            points[i as usize]
+        }
+    };
+    let segments = if cyclic { n } else { n - 1 };
+    let mut out = Vec::with_capacity(segments * steps + 1);
+    for i in 0..segments as isize {
+        let p = [at(i - 1), at(i), at(i + 1), at(i + 2)];
+        let mut t = [0.0f32; 4];
+        for k in 1..4 {
+            let (dx, dy) = (p[k].x - p[k - 1].x, p[k].y - p[k - 1].y);
+            t[k] = t[k - 1] + dx.hypot(dy).sqrt().max(1e-6);
+        }
+        out.push(p[1]);
+        for step in 1..steps {
+            let u = t[1] + (t[2] - t[1]) * step as f32 / steps as f32;
+            let blend = |a: Point, b: Point, ta: f32, tb: f32| lerp(a, b, (u - ta) / (tb - ta));
+            let a1 = blend(p[0], p[1], t[0], t[1]);
+            let a2 = blend(p[1], p[2], t[1], t[2]);
+            let a3 = blend(p[2], p[3], t[2], t[3]);
+            let b1 = blend(a1, a2, t[0], t[2]);
+            let b2 = blend(a2, a3, t[1], t[3]);
+            out.push(blend(b1, b2, t[1], t[2]));
+        }
+    }
+    if !cyclic {
+        out.push(points[n - 1]);
+    }
+    out
+}
+
+fn smooth_run(points: &[RichPoint], cyclic: bool, options: &SmoothOptions) -> Vec<RichPoint> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    match options.method {
+        Method::Chaikin => {
+            let mut points = points.to_vec();
+            for _ in 0..options.steps {
+                points = chaikin(&points, cyclic);
+            }
+            points
+        }
+        Method::CatmullRom => {
+            let geometry = |interpolated: bool| -> Vec<Point> {
+                let points: Vec<Point> = points.iter().map(|p| p.point(interpolated)).collect();
+                catmull_rom(&points, cyclic, options.steps.max(1))
+            };
+            geometry(false)
+                .into_iter()
+                .zip(geometry(true))
+                .map(|(raw_point, interpolated_point)| RichPoint {
+                    raw_point,
+                    interpolated_point,
+                })
+                .collect()
+        }
+    }
+}
+
+impl Path {
+    /// A smoothed copy of the path. Its ends and any points on the border of
+    /// the field stay put, and the runs between them are smoothed apart.
+    pub fn smooth(&self, options: &SmoothOptions) -> Path {
+        let n = self.points.len();
+        if n < 3 || options.steps == 0 {
+            return self.clone();
+        }
+
+        let mut points = self.points.clone();
+        if self.closed {
+            points.pop();
+            match points.iter().position(|p| pinned(p, options.extent)) {
+                None => {
+                    let mut points = smooth_run(&points, true, options);
+                    points.push(points[0].clone());
+                    return Path {
+                        points,
+                        closed: true,
+                    };
+                }
+                // Start and end on a pinned point, and carry on as if open.
+                Some(pin) => {
+                    points.rotate_left(pin);
+                    points.push(points[0].clone());
+                }
+            }
+        }
+
+        let last = points.len() - 1;
+        let pins: Vec<usize> = (0..=last)
+            .filter(|&i| i == 0 || i == last || pinned(&points[i], options.extent))
+            .collect();
+        let mut smoothed = vec![points[0].clone()];
+        for run in pins.windows(2) {
+            let run = smooth_run(&points[run[0]..=run[1]], false, options);
+            smoothed.extend(run.into_iter().skip(1));
+        }
+        Path {
+            points: smoothed,
+            closed: self.closed,
+        }
+    }
+}
+
+/// Smooths the paths of every level.
+pub fn smooth_levels(levels: &mut [ContourLevel], options: &SmoothOptions) {
+    for path in levels.iter_mut().flat_map(|level| &mut level.paths) {
+        *path = path.smooth(options);
+    }
+}
+
+/// The cubic Bézier segments of a curve through the points of the path,
+/// as the two control points and the end of each, starting from its first
+/// point. Points on the border of the field get no tangent, so the curve
+/// doesn't bulge out of it.
+pub fn cubic_segments(path: &Path, extent: (u32, u32), interpolated: bool) -> Vec<[Point; 3]> {
+    let points: Vec<Point> = path.points.iter().map(|p| p.point(interpolated)).collect();
+    let n = points.len();
+    // A closed path repeats its first point at the end, so its neighbours
+    // wrap around past that.
+    let neighbours = |i: usize| -> (Point, Point) {
+        if path.closed && (i == 0 || i == n - 1) {
+            (points[n.saturating_sub(2)], points[1.min(n - 1)])
+        } else {
+            (points[i.saturating_sub(1)], points[(i + 1).min(n - 1)])
+        }
+    };
+    let tangent = |i: usize| -> (f32, f32) {
+        if on_border(points[i], extent) {
+            return (0.0, 0.0);
+        }
+        let (before, after) = neighbours(i);
+        ((after.x - before.x) / 6.0, (after.y - before.y) / 6.0)
+    };
+
+    points
+        .windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            let (start, end) = (tangent(i), tangent(i + 1));
+            [
+                Point {
+                    x: w[0].x + start.0,
+                    y: w[0].y + start.1,
+                },
+                Point {
+                    x: w[1].x - end.0,
+                    y: w[1].y - end.1,
+                },
+                w[1],
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(path: &Path) -> Vec<(f32, f32)> {
+        path.points
+            .iter()
+            .map(|p| (p.interpolated_point.x, p.interpolated_point.y))
+            .collect()
+    }
+
+    #[test]
+    fn chaikin_test() {
+        let options = SmoothOptions {
+            method: Method::Chaikin,
+            steps: 1,
+            extent: (10, 10),
+        };
+        let line = Path::from_xy(&[(0.0, 4.0), (4.0, 4.0), (4.0, 8.0)], false);
+        assert_eq!(
+            vec![(0.0, 4.0), (3.0, 4.0), (4.0, 5.0), (4.0, 8.0)],
+            coordinates(&line.smooth(&options))
+        );
+
+        let ring = Path::from_xy(
+            &[(2.0, 2.0), (6.0, 2.0), (6.0, 6.0), (2.0, 6.0), (2.0, 2.0)],
+            true,
+        );
+        let smoothed = ring.smooth(&options);
+        assert!(smoothed.closed);
+        assert_eq!(
+            vec![
+                (3.0, 2.0),
+                (5.0, 2.0),
+                (6.0, 3.0),
+                (6.0, 5.0),
+                (5.0, 6.0),
+                (3.0, 6.0),
+                (2.0, 5.0),
+                (2.0, 3.0),
+                (3.0, 2.0)
+            ],
+            coordinates(&smoothed)
+        );
+    }
+
+    #[test]
+    fn pinned_test() {
+        // A ring closed along the left edge keeps that edge straight and
+        // its corners where they are.
+        let options = SmoothOptions {
+            method: Method::Chaikin,
+            steps: 3,
+            extent: (10, 10),
+        };
+        let ring = Path::from_xy(
+            &[(0.0, 2.0), (4.0, 2.0), (4.0, 6.0), (0.0, 6.0), (0.0, 2.0)],
+            true,
+        );
+        let smoothed = ring.smooth(&options);
+        let points = coordinates(&smoothed);
+        assert!(smoothed.closed);
+        assert_eq!(points.first(), points.last());
+        assert!(points.contains(&(0.0, 2.0)) && points.contains(&(0.0, 6.0)));
+        assert!(points.iter().all(|&(x, _)| x >= 0.0));
+        // Nothing between the two pins on the edge.
+        assert_eq!((0.0, 6.0), points[points.len() - 2]);
+    }
+
+    #[test]
+    fn catmull_rom_test() {
+        let options = SmoothOptions {
+            method: Method::CatmullRom,
+            steps: 4,
+            extent: (20, 20),
+        };
+        let line = Path::from_xy(&[(1.0, 5.0), (3.0, 3.0), (5.0, 5.0), (7.0, 3.0)], false);
+        let smoothed = line.smooth(&options);
+        let points = coordinates(&smoothed);
+        assert_eq!(13, points.len());
+        // It passes through every point it was given.
+        for (i, point) in coordinates(&line).into_iter().enumerate() {
+            assert_eq!(point, points[4 * i]);
+        }
+        // Symmetric about the middle point.
+        let (a, b) = (points[5], points[7]);
+        assert!((a.0 + b.0 - 8.0).abs() < 1e-5 && (a.1 - (8.0 - b.1)).abs() < 1e-5);
+
+        let ring = Path::from_xy(
+            &[(5.0, 5.0), (9.0, 5.0), (9.0, 9.0), (5.0, 9.0), (5.0, 5.0)],
+            true,
+        );
+        let smoothed = ring.smooth(&options);
+        assert_eq!(17, smoothed.points.len());
+        assert_eq!(smoothed.start(), smoothed.end());
+    }
+
+    #[test]
+    fn cubic_test() {
+        let line = Path::from_xy(&[(0.0, 3.0), (3.0, 3.0), (6.0, 6.0)], false);
+        assert_eq!(
+            vec![
+                [
+                    Point { x: 0.0, y: 3.0 },
+                    Point { x: 2.0, y: 2.5 },
+                    Point { x: 3.0, y: 3.0 }
+                ],
+                [
+                    Point { x: 4.0, y: 3.5 },
+                    Point { x: 6.0, y: 6.0 },
+                    Point { x: 6.0, y: 6.0 }
+                ],
+            ],
+            cubic_segments(&line, (7, 7), true)
+        );
+    }
+}
@@ -0,0 +1,367 @@
+use std::io::{self, Write};
+
+use itertools::join;
+
+use crate::{
+    band::ContourBand,
+    geo::{GeoTransform, Geometry},
+    labels::{self, Label, LabelOptions},
+    marching_squares::{ContourKind, ContourLevel},
+    polygon::Polygon,
+    smooth,
+    util::{Path, Point},
+};
+
+pub const DEFAULT_PALETTE: [&str; 16] = [
+    "#ffd8ba", "#f7a983", "#f28a91", "#db3b5d", "#57253b", "#ac2925", "#ef692f", "#eca549",
+    "#3e88b7", "#4b3b9c", "#6a6c56", "#adac8e", "#fff4e0", "#cecfbf", "#939487", "#2b2b26",
+];
+
+#[derive(Debug, Clone)]
+pub struct SvgOptions {
+    /// Stroke colours, cycled through from the last entry for the lowest level
+    pub palette: Vec<String>,
+    pub geometry: Geometry,
+    /// Draw paths as cubic Bézier curves through their points instead of
+    /// straight lines between them
+    pub curves: bool,
+    /// Label contours with their elevation, leaving a gap in the line under
+    /// each label
+    pub labels: Option<LabelOptions>,
+    /// Stroke width of index contours as a multiple of the others
+    pub index_width: f64,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        Self {
+            palette: DEFAULT_PALETTE.iter().map(|&c| c.to_owned()).collect(),
+            geometry: Geometry::default(),
+            curves: false,
+            labels: None,
+            index_width: 2.0,
+        }
+    }
+}
+
+impl SvgOptions {
+    pub fn colour(&self, level_index: usize) -> &str {
+        let len = self.palette.len();
+        &self.palette[(len - 1) - (level_index % len)]
+    }
+
+    fn level_width(&self, level: &ContourLevel) -> f64 {
+        match level.kind {
+            Some(ContourKind::Index) => self.geometry.pixel_size() * self.index_width,
+            _ => self.geometry.pixel_size(),
+        }
+    }
+
+    fn path_data(&self, path: &Path, extent: (u32, u32)) -> String {
+        if self.curves {
+            return self.curve_data(path, extent);
+        }
+        let Some(transform) = &self.geometry.transform else {
+            return path.to_svg(self.geometry.interpolated);
+        };
+        let points = path.points.iter().map(|p| {
+            let (x, y) = transform.apply(p.point(self.geometry.interpolated));
+            format!("{x} {y}")
+        });
+        format!(
+            "M {}{}",
+            join(points, " L "),
+            if path.closed { " Z" } else { "" }
+        )
+    }
+
+    // Pixel coordinates are shifted by half a pixel like in `Path::to_svg`.
+    fn coordinates(&self, p: Point) -> String {
+        match &self.geometry.transform {
+            Some(transform) => {
+                let (x, y) = transform.apply(p);
+                format!("{x} {y}")
+            }
+            None => format!("{} {}", p.x + 0.5, p.y + 0.5),
+        }
+    }
+
+    // Coordinates outside the group that flips map units upright.
+    fn screen_coordinates(&self, p: Point) -> (f64, f64) {
+        match &self.geometry.transform {
+            Some(transform) => {
+                let (x, y) = transform.apply(p);
+                (x, if transform.flips() { -y } else { y })
+            }
+            None => ((p.x + 0.5) as f64, (p.y + 0.5) as f64),
+        }
+    }
+
+    fn curve_data(&self, path: &Path, extent: (u32, u32)) -> String {
+        let coordinates = |p: Point| self.coordinates(p);
+        // Segments without tangents at either end are straight.
+        let mut from = path.points[0].point(self.geometry.interpolated);
+        let start = coordinates(from);
+        let segments = smooth::cubic_segments(path, extent, self.geometry.interpolated)
+            .into_iter()
+            .map(|[c1, c2, to]| {
+                let straight = c1 == from && c2 == to;
+                from = to;
+                if straight {
+                    format!(" L {}", coordinates(to))
+                } else {
+                    format!(
+                        " C {} {} {}",
+                        coordinates(c1),
+                        coordinates(c2),
+                        coordinates(to)
+                    )
+                }
+            });
+        format!(
+            "M {start}{}{}",
+            join(segments, ""),
+            if path.closed { " Z" } else { "" }
+        )
+    }
+
+    fn polygon_data(&self, polygon: &Polygon, extent: (u32, u32)) -> String {
+        if self.geometry.transform.is_none() && !self.curves {
+            return polygon.to_svg(self.geometry.interpolated);
+        }
+        join(
+            std::iter::once(&polygon.exterior)
+                .chain(&polygon.holes)
+                .map(|ring| self.path_data(ring, extent)),
+            " ",
+        )
+    }
+}
+
+// The smallest and largest coordinates covered by the outside corners of the
+// corner pixels, before any flip.
+fn bounds(extent: (u32, u32), transform: &GeoTransform) -> ((f64, f64), (f64, f64)) {
+    let (w, h) = (extent.0 as f32 - 0.5, extent.1 as f32 - 0.5);
+    let corners =
+        [(-0.5, -0.5), (w, -0.5), (w, h), (-0.5, h)].map(|(x, y)| transform.apply(Point { x, y }));
+    let (mut min, mut max) = (corners[0], corners[0]);
+    for (x, y) in corners {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    (min, max)
+}
+
+// Opens the document, and in map units a group that flips it back upright
+// when y runs north.
+fn write_header<W: Write>(out: &mut W, extent: (u32, u32), options: &SvgOptions) -> io::Result<()> {
+    let Some(transform) = &options.geometry.transform else {
+        return writeln!(
+            out,
+            "<svg width=\"{}\" height=\"{}\" version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\">",
+            extent.0, extent.1
+        );
+    };
+
+    let (min, max) = bounds(extent, transform);
+    let flips = transform.flips();
+    writeln!(
+        out,
+        "<svg width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\" version=\"1.1\" xmlns=\"http://www.w3.org/2000/svg\">",
+        extent.0,
+        extent.1,
+        min.0,
+        if flips { -max.1 } else { min.1 },
+        max.0 - min.0,
+        max.1 - min.1
+    )?;
+    let scale = if flips { "1 -1" } else { "1 1" };
+    writeln!(out, "<g transform=\"scale({scale})\" >")
+}
+
+fn write_footer<W: Write>(out: &mut W, options: &SvgOptions) -> io::Result<()> {
+    close_group(out, options)?;
+    writeln!(out, "</svg>")
+}
+
+fn close_group<W: Write>(out: &mut W, options: &SvgOptions) -> io::Result<()> {
+    if options.geometry.transform.is_some() {
+        writeln!(out, "</g>")?;
+    }
+    Ok(())
+}
+
+// A mask over the whole drawing with a gap along the stretch of contour
+// under every label.
+fn write_label_mask<W: Write>(
+    out: &mut W,
+    extent: (u32, u32),
+    labels: &[Label],
+    label_options: &LabelOptions,
+    options: &SvgOptions,
+) -> io::Result<()> {
+    let (min, max) = match &options.geometry.transform {
+        Some(transform) => bounds(extent, transform),
+        None => ((0.0, 0.0), (extent.0 as f64, extent.1 as f64)),
+    };
+    let area = format!(
+        "x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"",
+        min.0,
+        min.1,
+        max.0 - min.0,
+        max.1 - min.1
+    );
+    let width = label_options.font_size as f64 * 1.2 * options.geometry.pixel_size();
+    writeln!(
+        out,
+        "<defs><mask id=\"label-gaps\" maskUnits=\"userSpaceOnUse\" {area} >"
+    )?;
+    writeln!(out, "<rect {area} fill=\"white\" />")?;
+    for label in labels {
+        let points = label.baseline.iter().map(|&p| options.coordinates(p));
+        writeln!(
+            out,
+            "<path d=\"M {}\" stroke=\"black\" stroke-width=\"{width}\" fill=\"none\" />",
+            join(points, " L ")
+        )?;
+    }
+    writeln!(out, "</mask></defs>")
+}
+
+// Text along the labelled stretches, written outside any flipped group so it
+// reads upright.
+fn write_label_text<W: Write>(
+    out: &mut W,
+    labels: &[Label],
+    label_options: &LabelOptions,
+    options: &SvgOptions,
+) -> io::Result<()> {
+    writeln!(out, "<defs>")?;
+    for (i, label) in labels.iter().enumerate() {
+        let mut points: Vec<(f64, f64)> = label
+            .baseline
+            .iter()
+            .map(|&p| options.screen_coordinates(p))
+            .collect();
+        if points[points.len() - 1].0 < points[0].0 {
+            points.reverse();
+        }
+        let points = points.iter().map(|(x, y)| format!("{x} {y}"));
+        writeln!(
+            out,
+            "<path id=\"label-{i}\" d=\"M {}\" />",
+            join(points, " L ")
+        )?;
+    }
+    writeln!(out, "</defs>")?;
+
+    let size = label_options.font_size as f64 * options.geometry.pixel_size();
+    for (i, label) in labels.iter().enumerate() {
+        writeln!(
+            out,
+            "<text font-size=\"{size}\" fill=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" ><textPath href=\"#label-{i}\" startOffset=\"50%\">{}</textPath></text>",
+            options.colour(label.level),
+            label.text
+        )?;
+    }
+    Ok(())
+}
+
+/// Writes one `<g>` per level, filling closed paths with the level's colour.
+/// With labels the fills and the masked lines of every level go in separate
+/// groups, so the gaps under the labels don't cut through the fills.
+pub fn write_svg<W: Write>(
+    out: &mut W,
+    extent: (u32, u32),
+    levels: &[ContourLevel],
+    options: &SvgOptions,
+) -> io::Result<()> {
+    write_header(out, extent, options)?;
+
+    let Some(label_options) = &options.labels else {
+        write_levels(out, extent, levels, options)?;
+        return write_footer(out, options);
+    };
+    let labels = labels::place_labels(levels, extent, label_options, options.geometry.interpolated);
+    write_label_mask(out, extent, &labels, label_options, options)?;
+    for (i, level) in levels.iter().enumerate() {
+        let colour = options.colour(i);
+        let width = options.level_width(level);
+        writeln!(out, "<g stroke=\"none\" fill=\"{colour}\" >")?;
+        for path in level
+            .paths
+            .iter()
+            .filter(|p| p.closed && p.points.len() > 2)
+        {
+            writeln!(out, "<path d=\"{}\" />", options.path_data(path, extent))?;
+        }
+        writeln!(out, "</g>")?;
+        writeln!(
+            out,
+            "<g stroke=\"{colour}\" stroke-width=\"{width}\" fill=\"none\" mask=\"url(#label-gaps)\" >"
+        )?;
+        for path in level.paths.iter().filter(|p| p.points.len() > 2) {
+            writeln!(out, "<path d=\"{}\" />", options.path_data(path, extent))?;
+        }
+        writeln!(out, "</g>")?;
+    }
+    close_group(out, options)?;
+    write_label_text(out, &labels, label_options, options)?;
+    writeln!(out, "</svg>")
+}
+
+fn write_levels<W: Write>(
+    out: &mut W,
+    extent: (u32, u32),
+    levels: &[ContourLevel],
+    options: &SvgOptions,
+) -> io::Result<()> {
+    for (i, level) in levels.iter().enumerate() {
+        let colour = options.colour(i);
+        let width = options.level_width(level);
+        writeln!(
+            out,
+            "<g stroke=\"{colour}\" stroke-width=\"{width}\" fill=\"none\" >"
+        )?;
+        for path in level.paths.iter().filter(|p| p.points.len() > 2) {
+            let fill = if path.closed { colour } else { "none" };
+            writeln!(
+                out,
+                "<path fill=\"{fill}\" d=\"{}\" />",
+                options.path_data(path, extent)
+            )?;
+        }
+        writeln!(out, "</g>")?;
+    }
+    Ok(())
+}
+
+/// Writes one filled `<g>` per band, taking the colours of the palette in
+/// order from the lowest band.
+pub fn write_bands_svg<W: Write>(
+    out: &mut W,
+    extent: (u32, u32),
+    bands: &[ContourBand],
+    options: &SvgOptions,
+) -> io::Result<()> {
+    write_header(out, extent, options)?;
+
+    for (i, band) in bands.iter().enumerate() {
+        let colour = options.colour(i);
+        let width = options.geometry.pixel_size();
+        writeln!(
+            out,
+            "<g stroke=\"{colour}\" stroke-width=\"{width}\" fill=\"{colour}\" fill-rule=\"evenodd\" >"
+        )?;
+        for polygon in &band.polygons {
+            writeln!(
+                out,
+                "<path d=\"{}\" />",
+                options.polygon_data(polygon, extent)
+            )?;
+        }
+        writeln!(out, "</g>")?;
+    }
+
+    write_footer(out, options)
+}
@@ -0,0 +1,353 @@
+//! Contour tracing that walks from cell to cell across the edge each segment
+//! leaves through, instead of stitching loose lines back together.
+
+use crate::{
+    error::{Error, Result, Warning},
+    marching_squares::{id_from_vals, Field},
+    util::*,
+};
+
+// Set on a cell id when a saddle cell's centre is above the threshold.
+const CENTRE_ABOVE: u8 = 0b1_0000;
+// The id of cells that touch missing data and aren't contoured.
+const VOID: u8 = 0b10_0000;
+
+// The sides of a cell that each of its segments enters and leaves through,
+// oriented like `cell_segment` so the area above the threshold is on the right.
+fn cell_sides(id: u8) -> &'static [(Edge, Edge)] {
+    use Edge::*;
+    match id {
+        0b0001 => &[(Bottom, Right)],
+        0b1110 => &[(Right, Bottom)],
+        0b0010 => &[(Left, Bottom)],
+        0b1101 => &[(Bottom, Left)],
+        0b0011 => &[(Left, Right)],
+        0b1100 => &[(Right, Left)],
+        0b0100 => &[(Right, Top)],
+        0b1011 => &[(Top, Right)],
+        0b0101 => &[(Bottom, Top)],
+        0b1010 => &[(Top, Bottom)],
+        0b0111 => &[(Left, Top)],
+        0b1000 => &[(Top, Left)],
+        0b0110 => &[(Right, Top), (Left, Bottom)],
+        0b1001 => &[(Bottom, Right), (Top, Left)],
+        0b10110 => &[(Left, Top), (Right, Bottom)],
+        0b11001 => &[(Bottom, Left), (Top, Right)],
+        _ => &[],
+    }
+}
+
+fn opposite(side: Edge) -> Edge {
+    match side {
+        Edge::Left => Edge::Right,
+        Edge::Top => Edge::Bottom,
+        Edge::Right => Edge::Left,
+        Edge::Bottom => Edge::Top,
+    }
+}
+
+// The sample a side of a cell starts from, and whether the side runs along x.
+pub(crate) fn side_origin(cell: (u32, u32), side: Edge) -> ((u32, u32), bool) {
+    let (x, y) = cell;
+    match side {
+        Edge::Top => ((x, y), true),
+        Edge::Bottom => ((x, y + 1), true),
+        Edge::Left => ((x, y), false),
+        Edge::Right => ((x + 1, y), false),
+    }
+}
+
+struct Tracer<'a> {
+    field: &'a Field,
+    threshold: f32,
+    mode: Mode,
+    warnings: Vec<Warning>,
+    // Number of cells in each direction
+    cells: (u32, u32),
+    ids: Vec<u8>,
+    // One bit per segment of each cell
+    visited: Vec<u8>,
+}
+
+impl<'a> Tracer<'a> {
+    fn new(field: &'a Field, threshold: f32, mode: Mode) -> Self {
+        let cells = (field.extent.0 - 1, field.extent.1 - 1);
+        let mut ids = Vec::with_capacity(cells.0 as usize * cells.1 as usize);
+        for y in 0..cells.1 {
+            for x in 0..cells.0 {
+                let vals = [
+                    field.sample(x, y),
+                    field.sample(x + 1, y),
+                    field.sample(x, y + 1),
+                    field.sample(x + 1, y + 1),
+                ];
+                if !vals.iter().all(|&val| field.is_valid(val)) {
+                    ids.push(VOID);
+                    continue;
+                }
+                let id = id_from_vals(threshold, &vals);
+                let saddle = id == 0b0110 || id == 0b1001;
+                let centre = vals.iter().sum::<f32>() / 4.0;
+                ids.push(if saddle && centre > threshold {
+                    id | CENTRE_ABOVE
+                } else {
+                    id
+                });
+            }
+        }
+        let visited = vec![0; ids.len()];
+
+        Tracer {
+            field,
+            threshold,
+            mode,
+            warnings: Vec::new(),
+            cells,
+            ids,
+            visited,
+        }
+    }
+
+    fn index(&self, cell: (u32, u32)) -> usize {
+        cell.0 as usize + cell.1 as usize * self.cells.0 as usize
+    }
+
+    // The cell on the other side of `side`, unless it's outside the field or void.
+    fn neighbour(&self, cell: (u32, u32), side: Edge) -> Option<(u32, u32)> {
+        let (x, y) = cell;
+        let next = match side {
+            Edge::Left if x > 0 => (x - 1, y),
+            Edge::Top if y > 0 => (x, y - 1),
+            Edge::Right if x + 1 < self.cells.0 => (x + 1, y),
+            Edge::Bottom if y + 1 < self.cells.1 => (x, y + 1),
+            _ => return None,
+        };
+        (self.ids[self.index(next)] != VOID).then_some(next)
+    }
+
+    fn crossing(&self, cell: (u32, u32), side: Edge) -> RichPoint {
+        let (from, horizontal) = side_origin(cell, side);
+        self.field.crossing(from, horizontal, self.threshold)
+    }
+
+    fn trace(&mut self, start: (u32, u32), first_segment: usize) -> Result<Path> {
+        let sides = cell_sides(self.ids[self.index(start)]);
+        let mut points = vec![self.crossing(start, sides[first_segment].0)];
+
+        let (mut cell, mut segment) = (start, first_segment);
+        loop {
+            let i = self.index(cell);
+            self.visited[i] |= 1 << segment;
+            let exit = cell_sides(self.ids[i])[segment].1;
+            points.push(self.crossing(cell, exit));
+
+            let Some(next) = self.neighbour(cell, exit) else {
+                return Ok(Path {
+                    points,
+                    closed: false,
+                });
+            };
+            let entry = opposite(exit);
+            let next_sides = cell_sides(self.ids[self.index(next)]);
+            let next_segment = next_sides.iter().position(|&(from, _)| from == entry);
+            match next_segment {
+                Some(next_segment) if (next, next_segment) == (start, first_segment) => {
+                    return Ok(Path {
+                        points,
+                        closed: true,
+                    });
+                }
+                Some(next_segment) if self.visited[self.index(next)] & (1 << next_segment) == 0 => {
+                    cell = next;
+                    segment = next_segment;
+                }
+                // Only reachable when the samples don't order consistently,
+                // such as when they're NaN.
+                _ => return self.dead_end(points),
+            }
+        }
+    }
+
+    fn dead_end(&mut self, points: Vec<RichPoint>) -> Result<Path> {
+        let point = points.last().expect("Paths start with a point").raw_point;
+        if self.mode == Mode::Strict {
+            return Err(Error::DegenerateTopology { point });
+        }
+        self.warnings.push(Warning::DeadEnd { point });
+        Ok(Path {
+            points,
+            closed: false,
+        })
+    }
+}
+
+impl Field {
+    // Where the contour crosses the grid edge leaving sample `from` to the right
+    // or downwards. Every cell sharing the edge computes it from the same samples
+    // in the same order, so they agree exactly.
+    pub(crate) fn crossing(&self, from: (u32, u32), horizontal: bool, threshold: f32) -> RichPoint {
+        let to = if horizontal {
+            (from.0 + 1, from.1)
+        } else {
+            (from.0, from.1 + 1)
+        };
+        let a = self.sample(from.0, from.1);
+        let b = self.sample(to.0, to.1);
+        let t = (threshold - a) / (b - a);
+
+        let (x, y) = (from.0 as f32, from.1 as f32);
+        let (interpolated_point, raw_point) = if horizontal {
+            (Point { x: x + t, y }, Point { x: x + 0.5, y })
+        } else {
+            (Point { x, y: y + t }, Point { x, y: y + 0.5 })
+        };
+        RichPoint {
+            raw_point,
+            interpolated_point,
+        }
+    }
+
+    /// Traces every contour at `threshold` in one pass over the cells.
+    ///
+    /// Open paths run from one edge of the field or of a void to another;
+    /// closed paths repeat their first point at the end. The area above the
+    /// threshold is always on the right hand side when following a path with
+    /// y pointing down. Anything lenient mode had to work around comes back
+    /// alongside the paths.
+    pub fn trace_paths(&self, threshold: f32, mode: Mode) -> Result<(Vec<Path>, Vec<Warning>)> {
+        if self.extent.0 < 2 || self.extent.1 < 2 {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let mut tracer = Tracer::new(self, threshold, mode);
+        let mut paths = Vec::new();
+
+        // Contours that touch the edge of the field or a void have to be
+        // started there, anything left over afterwards is a closed loop.
+        let (columns, rows) = tracer.cells;
+        let border = (0..columns)
+            .flat_map(|x| [(x, 0), (x, rows - 1)])
+            .chain((0..rows).flat_map(|y| [(0, y), (columns - 1, y)]));
+        let void_rows = if tracer.ids.contains(&VOID) { rows } else { 0 };
+        let cells = (0..void_rows).flat_map(|y| (0..columns).map(move |x| (x, y)));
+        for cell in border.chain(cells) {
+            let i = tracer.index(cell);
+            for (segment, &(entry, _)) in cell_sides(tracer.ids[i]).iter().enumerate() {
+                let unvisited = tracer.visited[i] & (1 << segment) == 0;
+                if unvisited && tracer.neighbour(cell, entry).is_none() {
+                    paths.push(tracer.trace(cell, segment)?);
+                }
+            }
+        }
+
+        for y in 0..rows {
+            for x in 0..columns {
+                let i = tracer.index((x, y));
+                for segment in 0..cell_sides(tracer.ids[i]).len() {
+                    if tracer.visited[i] & (1 << segment) == 0 {
+                        paths.push(tracer.trace((x, y), segment)?);
+                    }
+                }
+            }
+        }
+
+        Ok((paths, tracer.warnings))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signed_area(path: &Path) -> f32 {
+        path.points
+            .windows(2)
+            .map(|w| {
+                let (a, b) = (w[0].raw_point, w[1].raw_point);
+                a.x * b.y - b.x * a.y
+            })
+            .sum::<f32>()
+            / 2.0
+    }
+
+    #[test]
+    fn closed_loop_test() {
+        #[rustfmt::skip]
+        let field = Field::new((4, 4), vec![
+            0.0, 0.0, 0.0, 0.0,
+            0.0, 9.0, 9.0, 0.0,
+            0.0, 9.0, 9.0, 0.0,
+            0.0, 0.0, 0.0, 0.0,
+        ]).unwrap();
+
+        let paths = field.trace_paths(5.0, Mode::Strict).unwrap().0;
+        assert_eq!(1, paths.len());
+        let path = &paths[0];
+        assert!(path.closed);
+        assert_eq!(9, path.points.len());
+        assert_eq!(path.start(), path.end());
+        // Above the threshold is on the right, which winds positively.
+        assert!(signed_area(path) > 0.0);
+    }
+
+    #[test]
+    fn open_path_test() {
+        #[rustfmt::skip]
+        let field = Field::new((3, 3), vec![
+            0.0, 0.0, 9.0,
+            0.0, 9.0, 9.0,
+            9.0, 9.0, 9.0,
+        ]).unwrap();
+
+        let paths = field.trace_paths(5.0, Mode::Strict).unwrap().0;
+        assert_eq!(1, paths.len());
+        let raw: Vec<_> = paths[0].points.iter().map(|p| p.raw_point).collect();
+        assert!(!paths[0].closed);
+        assert_eq!(
+            vec![
+                Point { x: 0.0, y: 1.5 },
+                Point { x: 0.5, y: 1.0 },
+                Point { x: 1.0, y: 0.5 },
+                Point { x: 1.5, y: 0.0 },
+            ],
+            raw
+        );
+    }
+
+    #[test]
+    fn saddle_test() {
+        let hills = vec![9.0, 0.0, 0.0, 9.0];
+
+        // Low centre keeps the two corners apart.
+        let field = Field::new((2, 2), hills.clone()).unwrap();
+        let paths = field.trace_paths(5.0, Mode::Strict).unwrap().0;
+        assert_eq!(2, paths.len());
+        assert_eq!(Point { x: 0.5, y: 1.0 }, paths[0].start().raw_point);
+        assert_eq!(Point { x: 1.0, y: 0.5 }, paths[0].end().raw_point);
+
+        // High centre joins them into a ridge.
+        let paths = field.trace_paths(4.0, Mode::Strict).unwrap().0;
+        assert_eq!(2, paths.len());
+        assert_eq!(Point { x: 0.5, y: 1.0 }, paths[0].start().raw_point);
+        assert_eq!(Point { x: 0.0, y: 0.5 }, paths[0].end().raw_point);
+    }
+
+    #[test]
+    fn void_test() {
+        // The ridge is cut in two by missing data instead of running round it.
+        #[rustfmt::skip]
+        let field = Field::new((5, 3), vec![
+            0.0, 0.0, 0.0, 0.0, 0.0,
+            9.0, 9.0, -1.0, 9.0, 9.0,
+            0.0, 0.0, 0.0, 0.0, 0.0,
+        ]).unwrap().with_nodata(-1.0);
+
+        let paths = field.trace_paths(5.0, Mode::Strict).unwrap().0;
+        assert_eq!(4, paths.len());
+        assert!(paths
+            .iter()
+            .all(|path| !path.closed && path.points.len() == 2));
+        let ends: Vec<_> = paths.iter().map(|path| path.end().raw_point).collect();
+        assert!(ends.contains(&Point { x: 1.0, y: 0.5 }));
+        assert!(ends.contains(&Point { x: 3.0, y: 1.5 }));
+    }
+}
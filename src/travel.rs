@@ -0,0 +1,208 @@
+//! Ordering of plotter strokes to cut down on travel with the pen up.
+
+type Position = (f64, f64);
+
+fn distance(a: Position, b: Position) -> f64 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// Pen-up distance of drawing the strokes in order, starting from `start`.
+pub fn travel(start: Position, strokes: &[Vec<Position>]) -> f64 {
+    let mut position = start;
+    let mut total = 0.0;
+    for stroke in strokes.iter().filter(|s| !s.is_empty()) {
+        total += distance(position, stroke[0]);
+        position = stroke[stroke.len() - 1];
+    }
+    total
+}
+
+// The ends of a stroke in the order it's drawn, and whether that's backwards.
+#[derive(Debug, Clone, Copy)]
+struct Visit {
+    stroke: usize,
+    entry: Position,
+    exit: Position,
+    reversed: bool,
+    // Open strokes can only be drawn backwards when reversing is allowed.
+    // Closed strokes start and end in the same place, so flipping them
+    // changes nothing and they're always drawn as they are.
+    reversible: bool,
+}
+
+impl Visit {
+    fn flip(&mut self) {
+        if self.entry != self.exit {
+            std::mem::swap(&mut self.entry, &mut self.exit);
+            self.reversed = !self.reversed;
+        }
+    }
+}
+
+// Repeatedly draws whichever stroke starts or ends closest to the pen next.
+fn nearest_neighbour(start: Position, strokes: &[Vec<Position>], reverse: bool) -> Vec<Visit> {
+    let mut left: Vec<Visit> = strokes
+        .iter()
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(|(stroke, s)| Visit {
+            stroke,
+            entry: s[0],
+            exit: s[s.len() - 1],
+            reversed: false,
+            reversible: reverse || s[0] == s[s.len() - 1],
+        })
+        .collect();
+    let mut tour = Vec::with_capacity(left.len());
+    let mut position = start;
+    while !left.is_empty() {
+        let mut best = (f64::INFINITY, 0, false);
+        for (i, visit) in left.iter().enumerate() {
+            let forwards = distance(position, visit.entry);
+            if forwards < best.0 {
+                best = (forwards, i, false);
+            }
+            let backwards = distance(position, visit.exit);
+            if visit.reversible && backwards < best.0 {
+                best = (backwards, i, true);
+            }
+        }
+        let mut visit = left.swap_remove(best.1);
+        if best.2 {
+            visit.flip();
+        }
+        position = visit.exit;
+        tour.push(visit);
+    }
+    tour
+}
+
+// Reverses runs of the tour, drawing every stroke in them backwards, for as
+// long as that shortens the travel. The tour is open, so there's no edge
+// back to the start. Runs can't take in strokes that mustn't be reversed.
+fn two_opt(start: Position, tour: &mut [Visit], max_passes: usize) {
+    let n = tour.len();
+    for _ in 0..max_passes {
+        let mut improved = false;
+        for i in 0..n {
+            if !tour[i].reversible {
+                continue;
+            }
+            let before = if i == 0 { start } else { tour[i - 1].exit };
+            for j in i + 1..n {
+                if !tour[j].reversible {
+                    break;
+                }
+                let removed = distance(before, tour[i].entry)
+                    + tour
+                        .get(j + 1)
+                        .map_or(0.0, |next| distance(tour[j].exit, next.entry));
+                let added = distance(before, tour[j].exit)
+                    + tour
+                        .get(j + 1)
+                        .map_or(0.0, |next| distance(tour[i].entry, next.entry));
+                if added < removed - 1e-9 {
+                    tour[i..=j].reverse();
+                    tour[i..=j].iter_mut().for_each(Visit::flip);
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+/// Reorders the strokes to shorten the travel from `start`, and with
+/// `reverse` draws open strokes backwards where that helps. Returns where the
+/// pen ends up.
+pub fn optimise(start: Position, strokes: &mut Vec<Vec<Position>>, reverse: bool) -> Position {
+    let mut tour = nearest_neighbour(start, strokes, reverse);
+    two_opt(start, &mut tour, 10);
+
+    let mut taken: Vec<Option<Vec<Position>>> = strokes.drain(..).map(Some).collect();
+    strokes.extend(tour.iter().filter_map(|visit| {
+        let mut stroke = taken[visit.stroke].take()?;
+        if visit.reversed {
+            stroke.reverse();
+        }
+        Some(stroke)
+    }));
+    tour.last().map_or(start, |visit| visit.exit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn travel_test() {
+        let strokes = vec![vec![(0.0, 3.0), (1.0, 1.0)], vec![(4.0, 1.0), (5.0, 5.0)]];
+        assert_eq!(6.0, travel((0.0, 0.0), &strokes));
+        assert_eq!(0.0, travel((0.0, 0.0), &[]));
+    }
+
+    #[test]
+    fn optimise_test() {
+        // Strokes along a line, shuffled and half of them backwards.
+        let mut strokes = vec![
+            vec![(3.0, 0.0), (2.5, 0.0)],
+            vec![(0.5, 0.0), (1.0, 0.0)],
+            vec![(5.0, 0.0), (5.5, 0.0)],
+            vec![(2.0, 0.0), (1.5, 0.0)],
+            vec![(4.0, 0.0), (3.5, 0.0)],
+        ];
+        let before = travel((0.0, 0.0), &strokes);
+        let end = optimise((0.0, 0.0), &mut strokes, true);
+
+        assert_eq!((5.5, 0.0), end);
+        assert!(travel((0.0, 0.0), &strokes) < before);
+        assert_eq!(
+            vec![
+                vec![(0.5, 0.0), (1.0, 0.0)],
+                vec![(1.5, 0.0), (2.0, 0.0)],
+                vec![(2.5, 0.0), (3.0, 0.0)],
+                vec![(3.5, 0.0), (4.0, 0.0)],
+                vec![(5.0, 0.0), (5.5, 0.0)],
+            ],
+            strokes
+        );
+    }
+
+    #[test]
+    fn two_opt_test() {
+        // Nearest neighbour works up the column and then has to come back
+        // down past it, which reversing the first stretch avoids.
+        let mut strokes: Vec<_> = [(-4.0, -3.0), (-4.0, 3.0), (-4.0, 0.0), (-4.0, 1.0)]
+            .into_iter()
+            .map(|p| vec![p])
+            .collect();
+        let start = (0.0, 0.0);
+        let greedy: Vec<_> = nearest_neighbour(start, &strokes, true)
+            .iter()
+            .map(|visit| strokes[visit.stroke].clone())
+            .collect();
+        assert_eq!(13.0, travel(start, &greedy));
+
+        optimise(start, &mut strokes, true);
+        assert_eq!(11.0, travel(start, &strokes));
+    }
+
+    #[test]
+    fn direction_test() {
+        let open = vec![(2.0, 0.0), (1.0, 0.0)];
+        let closed = vec![(3.0, 0.0), (4.0, 1.0), (4.0, 0.0), (3.0, 0.0)];
+
+        // Without reversing, open strokes keep their direction.
+        let mut strokes = vec![closed.clone(), open.clone()];
+        optimise((0.0, 0.0), &mut strokes, false);
+        assert_eq!(vec![open.clone(), closed.clone()], strokes);
+
+        // Closed strokes are never drawn backwards.
+        let mut strokes = vec![closed.clone(), open.clone()];
+        optimise((0.0, 0.0), &mut strokes, true);
+        let backwards: Vec<_> = open.iter().rev().copied().collect();
+        assert_eq!(vec![backwards, closed], strokes);
+    }
+}
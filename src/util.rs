@@ -1,8 +1,12 @@
-use std::cell;
+use std::collections::{hash_map::Entry, HashMap, VecDeque};
 
 use itertools::join;
 
-use crate::marching_squares::CellLine;
+use crate::{
+    boundary::Boundary,
+    error::{Error, Result, Warning},
+    marching_squares::CellLine,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Point {
@@ -21,12 +25,36 @@ pub struct RichPoint {
     pub raw_point: Point,
     pub interpolated_point: Point,
 }
+impl RichPoint {
+    pub fn point(&self, interpolated: bool) -> Point {
+        if interpolated {
+            self.interpolated_point
+        } else {
+            self.raw_point
+        }
+    }
+}
 #[derive(Debug, Clone)]
 pub struct Path {
     pub points: Vec<RichPoint>,
     pub closed: bool,
 }
 impl Path {
+    /// A path through the points, with the same raw and interpolated geometry.
+    #[cfg(test)]
+    pub(crate) fn from_xy(points: &[(f32, f32)], closed: bool) -> Self {
+        Path {
+            points: points
+                .iter()
+                .map(|&(x, y)| RichPoint {
+                    raw_point: Point { x, y },
+                    interpolated_point: Point { x, y },
+                })
+                .collect(),
+            closed,
+        }
+    }
+
     pub fn start(&self) -> RichPoint {
         self.points.first().expect("Shouldn't be empty").to_owned()
     }
@@ -40,52 +68,125 @@ impl Path {
             + &(join(
                 self.points
                     .iter()
-                    .map(|p| {
-                        if interpolated {
-                            p.interpolated_point
-                        } else {
-                            p.raw_point
-                        }
-                    })
+                    .map(|p| p.point(interpolated))
                     .map(|p| format!("{} {}", p.x + 0.5, p.y + 0.5)),
                 " L ",
             ))
             + if self.closed { " Z" } else { "" }
     }
+
+    /// Shoelace area of a closed path, positive when it winds counter-clockwise
+    /// with y pointing up, which is clockwise on screen.
+    pub fn signed_area(&self, interpolated: bool) -> f32 {
+        let points: Vec<Point> = self.points.iter().map(|p| p.point(interpolated)).collect();
+        let twice_area: f64 = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .map(|(a, b)| a.x as f64 * b.y as f64 - b.x as f64 * a.y as f64)
+            .sum();
+        (twice_area / 2.0) as f32
+    }
+
+    /// Length along the path, all the way round for a closed path.
+    pub fn length(&self, interpolated: bool) -> f32 {
+        self.points
+            .windows(2)
+            .map(|w| {
+                let (a, b) = (w[0].point(interpolated), w[1].point(interpolated));
+                (b.x - a.x).hypot(b.y - a.y)
+            })
+            .sum()
+    }
+
+    /// Top left and bottom right corners of the box around the path.
+    pub fn bounds(&self, interpolated: bool) -> (Point, Point) {
+        let start = self.start().point(interpolated);
+        self.points
+            .iter()
+            .map(|p| p.point(interpolated))
+            .fold((start, start), |(min, max), p| {
+                (
+                    Point {
+                        x: min.x.min(p.x),
+                        y: min.y.min(p.y),
+                    },
+                    Point {
+                        x: max.x.max(p.x),
+                        y: max.y.max(p.y),
+                    },
+                )
+            })
+    }
+
+    /// Reverses the direction of the path, keeping a closed path closed.
+    pub fn reverse(&mut self) {
+        self.points.reverse();
+    }
+
+    /// Even-odd point in polygon test treating the path as a closed ring.
+    pub fn contains(&self, point: Point, interpolated: bool) -> bool {
+        let points: Vec<Point> = self.points.iter().map(|p| p.point(interpolated)).collect();
+        let mut inside = false;
+        for (a, b) in points.iter().zip(points.iter().cycle().skip(1)) {
+            if (a.y > point.y) != (b.y > point.y)
+                && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+            {
+                inside = !inside;
+            }
+        }
+        inside
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
-enum Edge {
+pub(crate) enum Edge {
     Left,
     Top,
     Right,
     Bottom,
 }
 
-fn on_edge(point: &RichPoint, extents: (u32, u32)) -> Option<Edge> {
-    let (width, height) = extents;
-    let Point { x, y } = point.raw_point;
-    if x <= 0.5 {
-        return Some(Edge::Left);
-    }
-    if y <= 0.5 {
-        return Some(Edge::Top);
-    }
-    if x >= (extents.0 as f32 - 1.0) {
-        return Some(Edge::Right);
-    }
-    if y >= (extents.1 as f32 - 1.0) {
-        return Some(Edge::Bottom);
-    }
-    None
-}
-
 pub enum CloseEdges {
     None,
     ForExtent(u32, u32),
 }
-pub fn paths_from_lines(lines: &[CellLine], close_edges: CloseEdges) -> Vec<Path> {
-    let mut paths: Vec<Path> = Vec::new();
+
+/// What to do when the contour topology can't be resolved.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Fail with an error.
+    #[default]
+    Strict,
+    /// Keep the fragments as separate paths and report a [`Warning`].
+    Lenient,
+}
+
+// Paths are only ever joined where the raw points match exactly, so the bit
+// patterns make a sound hash key.
+type EndpointKey = (u32, u32);
+
+fn endpoint_key(point: &RichPoint) -> EndpointKey {
+    (point.raw_point.x.to_bits(), point.raw_point.y.to_bits())
+}
+
+struct Stitch {
+    points: VecDeque<RichPoint>,
+    closed: bool,
+    // Position the path would have had in the output if it were kept in a
+    // single vector, merged paths move to the back.
+    order: usize,
+}
+
+pub fn paths_from_lines(
+    lines: &[CellLine],
+    close_edges: CloseEdges,
+    mode: Mode,
+) -> Result<(Vec<Path>, Vec<Warning>)> {
+    let mut warnings = Vec::new();
+    let mut stitches: Vec<Option<Stitch>> = Vec::new();
+    let mut by_start: HashMap<EndpointKey, usize> = HashMap::new();
+    let mut by_end: HashMap<EndpointKey, usize> = HashMap::new();
+    let mut next_order = 0..;
 
     for cell_line in lines {
         let line = &cell_line.interpolated_line;
@@ -101,172 +202,167 @@ pub fn paths_from_lines(lines: &[CellLine], close_edges: CloseEdges) -> Vec<Path
             raw_point: raw_line.end,
         };
 
-        let mut matching_start: Option<u32> = None;
-        let mut matching_end: Option<u32> = None;
-        for (i, path) in paths.iter().enumerate() {
-            if path.end().raw_point == line_start.raw_point {
-                if matching_start.is_some() {
-                    println!("i:  {i}, path: {path:?}, line: {line:?}");
-                    let matching_path = &paths[matching_start.unwrap() as usize];
-                    println!("Previous match: {matching_start:?} - {matching_path:?}");
-                    println!("Previous match: {matching_start:?}");
-                    panic!("Multiple matches???")
-                }
-                matching_start = Some(i as u32);
+        match (
+            by_end.remove(&endpoint_key(&line_start)),
+            by_start.remove(&endpoint_key(&line_end)),
+        ) {
+            (Some(i_start), Some(i_end)) if i_start == i_end => {
+                let stitch = stitches[i_start].as_mut().expect("Indexed paths exist");
+                stitch.points.push_back(line_end);
+                stitch.closed = true;
             }
-            if path.start().raw_point == line_end.raw_point {
-                if matching_end.is_some() {
-                    println!("i:  {i}, path: {path:?}, line: {line:?}");
-                    let matching_path = &paths[matching_end.unwrap() as usize];
-                    println!("Previous match: {matching_end:?} - {matching_path:?}");
-                    panic!("Multiple matches???")
-                }
-                matching_end = Some(i as u32);
+            (Some(i_start), Some(i_end)) => {
+                let end_path = stitches[i_end].take().expect("Indexed paths exist");
+                let start_path = stitches[i_start].as_mut().expect("Indexed paths exist");
+
+                start_path.points.push_back(line_end);
+                start_path.points.extend(end_path.points);
+                start_path.order = next_order.next().unwrap();
+
+                let new_end = endpoint_key(start_path.points.back().unwrap());
+                by_end.insert(new_end, i_start);
             }
-        }
-        if matching_start.is_some() && matching_end.is_some() {
-            let i_start = matching_start.unwrap();
-            let i_end = matching_end.unwrap();
-
-            if i_start == i_end {
-                paths[i_start as usize].points.push(line_end);
-                paths[i_start as usize].closed = true;
-            } else {
-                let mut start_path;
-                let mut end_path;
-                if i_start < i_end {
-                    end_path = paths.remove(i_end as usize);
-                    start_path = paths.remove(i_start as usize);
-                } else {
-                    start_path = paths.remove(i_start as usize);
-                    end_path = paths.remove(i_end as usize);
-                }
-
-                start_path.points.push(line_end);
-                start_path.points.append(&mut end_path.points);
-                paths.push(start_path);
+            (Some(i_start), None) => {
+                insert_endpoint(&mut by_end, &line_end, i_start, mode, &mut warnings)?;
+                stitches[i_start]
+                    .as_mut()
+                    .expect("Indexed paths exist")
+                    .points
+                    .push_back(line_end);
+            }
+            (None, Some(i_end)) => {
+                insert_endpoint(&mut by_start, &line_start, i_end, mode, &mut warnings)?;
+                stitches[i_end]
+                    .as_mut()
+                    .expect("Indexed paths exist")
+                    .points
+                    .push_front(line_start);
+            }
+            (None, None) => {
+                let i = stitches.len();
+                insert_endpoint(&mut by_start, &line_start, i, mode, &mut warnings)?;
+                insert_endpoint(&mut by_end, &line_end, i, mode, &mut warnings)?;
+                stitches.push(Some(Stitch {
+                    points: VecDeque::from([line_start, line_end]),
+                    closed: false,
+                    order: next_order.next().unwrap(),
+                }));
             }
-        } else if let Some(i_start) = matching_start {
-            paths[i_start as usize].points.push(line_end);
-        } else if let Some(i_end) = matching_end {
-            paths[i_end as usize].points.insert(0, line_start);
-        } else {
-            paths.push(Path {
-                points: vec![line_start, line_end],
-                closed: false,
-            })
         }
     }
 
+    let mut stitches: Vec<Stitch> = stitches.into_iter().flatten().collect();
+    stitches.sort_by_key(|stitch| stitch.order);
+    let mut paths: Vec<Path> = stitches
+        .into_iter()
+        .map(|stitch| Path {
+            points: stitch.points.into(),
+            closed: stitch.closed,
+        })
+        .collect();
+
     if let CloseEdges::ForExtent(width, height) = close_edges {
-        for open_path in paths.iter_mut().filter(|p| !p.closed) {
-            let begin_edge = on_edge(&open_path.start(), (width, height));
-            let end_edge = on_edge(&open_path.end(), (width, height));
-            if begin_edge.is_none() || end_edge.is_none() {
-                continue;
-            }
-            let begin_edge = begin_edge.unwrap();
-            let end_edge = end_edge.unwrap();
-
-            let top_left = RichPoint {
-                raw_point: Point { x: 0.0, y: 0.0 },
-                interpolated_point: Point { x: 0.0, y: 0.0 },
-            };
-            let top_right = RichPoint {
-                raw_point: Point {
-                    x: width as f32 - 0.5,
-                    y: 0.0,
-                },
-                interpolated_point: Point {
-                    x: width as f32 - 0.5,
-                    y: 0.0,
-                },
-            };
-            let bottom_left = RichPoint {
-                raw_point: Point {
-                    x: 0.0,
-                    y: height as f32 - 0.5,
-                },
-                interpolated_point: Point {
-                    x: 0.0,
-                    y: height as f32 - 0.5,
-                },
-            };
-            let bottom_right = RichPoint {
-                raw_point: Point {
-                    x: width as f32 - 0.5,
-                    y: height as f32 - 0.5,
-                },
-                interpolated_point: Point {
-                    x: width as f32 - 0.5,
-                    y: height as f32 - 0.5,
-                },
-            };
-
-            match (begin_edge, end_edge) {
-                (Edge::Left, Edge::Left) => open_path.closed = true,
-                (Edge::Left, Edge::Top) => {
-                    open_path.points.push(top_right.clone());
-                    open_path.points.push(bottom_right.clone());
-                    open_path.points.push(bottom_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Left, Edge::Right) => {
-                    open_path.points.push(bottom_right.clone());
-                    open_path.points.push(bottom_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Left, Edge::Bottom) => {
-                    open_path.points.push(bottom_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Top, Edge::Left) => {
-                    open_path.points.push(top_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Top, Edge::Top) => open_path.closed = true,
-                (Edge::Top, Edge::Right) => {
-                    open_path.points.push(top_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Top, Edge::Bottom) => {
-                    open_path.points.push(bottom_left.clone());
-                    open_path.points.push(top_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Right, Edge::Left) => {
-                    open_path.points.push(top_left.clone());
-                    open_path.points.push(top_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Right, Edge::Top) => {
-                    open_path.points.push(top_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Right, Edge::Right) => open_path.closed = true,
-                (Edge::Right, Edge::Bottom) => {
-                    open_path.points.push(bottom_left.clone());
-                    open_path.points.push(top_left.clone());
-                    open_path.points.push(top_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Bottom, Edge::Left) => {
-                    open_path.points.push(bottom_left.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Bottom, Edge::Top) => {
-                    open_path.points.push(top_right.clone());
-                    open_path.points.push(bottom_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Bottom, Edge::Right) => {
-                    open_path.points.push(bottom_right.clone());
-                    open_path.closed = true;
-                }
-                (Edge::Bottom, Edge::Bottom) => open_path.closed = true,
-            };
+        Boundary::around((width, height)).close_paths(&mut paths, |_| false);
+    }
+
+    Ok((paths, warnings))
+}
+
+fn insert_endpoint(
+    index: &mut HashMap<EndpointKey, usize>,
+    point: &RichPoint,
+    path: usize,
+    mode: Mode,
+    warnings: &mut Vec<Warning>,
+) -> Result<()> {
+    match index.entry(endpoint_key(point)) {
+        Entry::Vacant(entry) => {
+            entry.insert(path);
+            Ok(())
+        }
+        Entry::Occupied(_) if mode == Mode::Lenient => {
+            warnings.push(Warning::Unjoined {
+                point: point.raw_point,
+            });
+            Ok(())
+        }
+        Entry::Occupied(_) => Err(Error::DegenerateTopology {
+            point: point.raw_point,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell_line(start: (f32, f32), end: (f32, f32)) -> CellLine {
+        let line = || Line {
+            start: Point {
+                x: start.0,
+                y: start.1,
+            },
+            end: Point { x: end.0, y: end.1 },
+        };
+        CellLine {
+            interpolated_line: line(),
+            raw_line: line(),
         }
     }
 
-    paths
+    fn xs(path: &Path) -> Vec<(f32, f32)> {
+        path.points
+            .iter()
+            .map(|p| (p.raw_point.x, p.raw_point.y))
+            .collect()
+    }
+
+    #[test]
+    fn stitch_test() {
+        // Two fragments that only meet through the last line, plus a loop.
+        let lines = [
+            cell_line((0.5, 0.0), (1.0, 0.5)),
+            cell_line((1.5, 1.0), (2.0, 1.5)),
+            cell_line((1.0, 0.5), (1.5, 1.0)),
+            cell_line((5.0, 5.5), (5.5, 5.0)),
+            cell_line((4.5, 5.0), (5.0, 5.5)),
+            cell_line((5.5, 5.0), (4.5, 5.0)),
+        ];
+        let (paths, warnings) = paths_from_lines(&lines, CloseEdges::None, Mode::Strict).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(2, paths.len());
+        assert!(!paths[0].closed);
+        assert_eq!(
+            vec![(0.5, 0.0), (1.0, 0.5), (1.5, 1.0), (1.5, 1.0), (2.0, 1.5)],
+            xs(&paths[0])
+        );
+        assert!(paths[1].closed);
+        assert_eq!(
+            vec![(4.5, 5.0), (5.0, 5.5), (5.5, 5.0), (4.5, 5.0)],
+            xs(&paths[1])
+        );
+    }
+
+    #[test]
+    fn degenerate_test() {
+        // Two lines leaving the same point can't both be stitched to it.
+        let lines = [
+            cell_line((0.5, 0.0), (1.0, 0.5)),
+            cell_line((0.5, 0.0), (0.0, 0.5)),
+        ];
+        assert!(matches!(
+            paths_from_lines(&lines, CloseEdges::None, Mode::Strict),
+            Err(Error::DegenerateTopology { .. })
+        ));
+
+        let (paths, warnings) = paths_from_lines(&lines, CloseEdges::None, Mode::Lenient).unwrap();
+        assert_eq!(2, paths.len());
+        assert_eq!(
+            vec![Warning::Unjoined {
+                point: Point { x: 0.5, y: 0.0 }
+            }],
+            warnings
+        );
+    }
 }